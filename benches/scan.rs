@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use sched::clock::SystemClock;
+use sched::storage::{Codec, SledBackend, Storage};
+
+const LOGS: u32 = 20_000;
+
+/// A sled store at a temporary path with `LOGS` logs encoded with `codec`
+fn fill(codec: Codec) -> (PathBuf, Storage) {
+    let path = env::temp_dir().join(format!("sched-bench-{}-{}", codec.name(), process::id()));
+    let _ = fs::remove_dir_all(&path);
+    let backend = SledBackend::open(&path);
+    let mut store = Storage::new(Box::new(backend), Arc::new(SystemClock), codec).unwrap();
+    for i in 0..LOGS {
+        let mut attrs = BTreeMap::new();
+        attrs.insert("id".to_string(), (i % 100).into());
+        store.create_log("task.finish".into(), attrs).unwrap();
+    }
+    (path, store)
+}
+
+/// Scan all the logs with `find_log`, as statistics and agendas do
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_log");
+    group.throughput(Throughput::Elements(LOGS as u64));
+    for &codec in &[Codec::Json, Codec::Cbor] {
+        let (path, mut store) = fill(codec);
+        group.bench_function(codec.name(), |b| {
+            b.iter(|| store.find_log(|l| l.attrs["id"] == 42, Some(usize::MAX)).len())
+        });
+        drop(store);
+        let _ = fs::remove_dir_all(path);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
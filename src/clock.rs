@@ -0,0 +1,140 @@
+#[cfg(test)]
+use std::sync::Mutex;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+
+/// Source of the current time, so that tests can freeze it and previews can move it
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Real time flowing from another starting point, for previewing how things look at that time
+pub struct ShiftedClock {
+    start: DateTime<Utc>,
+    since: Instant,
+}
+
+impl ShiftedClock {
+    pub fn starting_at(start: DateTime<Utc>) -> ShiftedClock {
+        ShiftedClock {
+            start,
+            since: Instant::now(),
+        }
+    }
+}
+
+impl Clock for ShiftedClock {
+    fn now(&self) -> DateTime<Utc> {
+        // Elapsed times that don't fit are centuries long
+        self.start + Duration::from_std(self.since.elapsed()).unwrap_or_else(|_| Duration::zero())
+    }
+}
+
+/// A clock that only moves when told to
+#[cfg(test)]
+pub struct FakeClock(Mutex<DateTime<Utc>>);
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> FakeClock {
+        FakeClock(Mutex::new(now))
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.0.lock().unwrap();
+        *now = *now + by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// The local timezone of sched: the system one, unless another was picked with `set_zone`
+#[derive(Clone, Copy, Debug)]
+pub struct LocalZone;
+
+impl TimeZone for LocalZone {
+    type Offset = FixedOffset;
+
+    fn from_offset(_: &FixedOffset) -> LocalZone {
+        LocalZone
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+        match *ZONE.read().unwrap() {
+            Some(tz) => tz.offset_from_local_date(local).map(|o| o.fix()),
+            None => Local.offset_from_local_date(local),
+        }
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+        match *ZONE.read().unwrap() {
+            Some(tz) => tz.offset_from_local_datetime(local).map(|o| o.fix()),
+            None => Local.offset_from_local_datetime(local),
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+        match *ZONE.read().unwrap() {
+            Some(tz) => tz.offset_from_utc_date(utc).fix(),
+            None => Local.offset_from_utc_date(utc),
+        }
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+        match *ZONE.read().unwrap() {
+            Some(tz) => tz.offset_from_utc_datetime(utc).fix(),
+            None => Local.offset_from_utc_datetime(utc),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CLOCK: RwLock<Arc<dyn Clock>> = RwLock::new(Arc::new(SystemClock));
+    static ref ZONE: RwLock<Option<Tz>> = RwLock::new(None);
+}
+
+/// Replace the clock used by sched. Must be done before the store and the job runner start, which keep the
+/// clock they started with
+pub fn set(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+pub fn get() -> Arc<dyn Clock> {
+    CLOCK.read().unwrap().clone()
+}
+
+pub fn now() -> DateTime<Utc> {
+    CLOCK.read().unwrap().now()
+}
+
+pub fn local_now() -> DateTime<LocalZone> {
+    now().with_timezone(&LocalZone)
+}
+
+/// Make `LocalZone` follow `zone`, or the system timezone for `None`. Unlike setting `TZ`, this is safe with
+/// other threads running
+pub fn set_zone(zone: Option<Tz>) {
+    *ZONE.write().unwrap() = zone;
+}
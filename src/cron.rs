@@ -0,0 +1,244 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use thiserror::Error;
+
+use crate::clock::LocalZone;
+
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum CronError {
+    #[error("Expected 5 or 6 fields in cron expression, got {0}")]
+    FieldCount(usize),
+    #[error("Invalid cron field '{0}'")]
+    Field(String),
+    #[error("Value {0} out of range in cron field '{1}'")]
+    Range(u32, String),
+}
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+/// Sunday is both 0 and 7, so that ranges can end on it (e.g. `MON-SUN`)
+const WEEKDAYS: [&str; 8] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT", "SUN"];
+
+/// How far ahead to look for the next matching time before giving up, so that impossible expressions
+/// (e.g. `0 0 30 2 *`) don't loop forever
+const MAX_YEARS: i32 = 8;
+
+/// A parsed cron expression, with an optional leading seconds field. Each field is stored as a bitset of the
+/// allowed values
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day-of-month/day-of-week starts with `*`. When both are restricted, a day matching either counts
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parse a number or a name. Names listed twice are the first value, or the last one at the end of a range
+fn parse_value(s: &str, names: &[&str], offset: u32, range_end: bool) -> Option<u32> {
+    s.parse().ok().or_else(|| {
+        let upper = s.to_ascii_uppercase();
+        let index = if range_end {
+            names.iter().rposition(|n| *n == upper)
+        } else {
+            names.iter().position(|n| *n == upper)
+        };
+        index.map(|i| i as u32 + offset)
+    })
+}
+
+/// Parse one field into a bitset. `names` are the names of the values starting from `min`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, CronError> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => {
+                let step = part[i + 1..]
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&s| s > 0)
+                    .ok_or_else(|| CronError::Field(field.into()))?;
+                (&part[..i], step)
+            }
+            None => (part, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            let lo = parse_value(&range[..i], names, min, false).ok_or_else(|| CronError::Field(field.into()))?;
+            let hi = parse_value(&range[i + 1..], names, min, true).ok_or_else(|| CronError::Field(field.into()))?;
+            (lo, hi)
+        } else {
+            let v = parse_value(range, names, min, false).ok_or_else(|| CronError::Field(field.into()))?;
+            // `a/n` means starting from `a` to the end
+            (v, if step > 1 { max } else { v })
+        };
+        for &v in &[lo, hi] {
+            if v < min || v > max {
+                return Err(CronError::Range(v, field.into()));
+            }
+        }
+        if lo > hi {
+            return Err(CronError::Field(field.into()));
+        }
+        for v in (lo..=hi).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, v: u32) -> bool {
+    bits & (1 << v) != 0
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(s: &str) -> Result<Cron, CronError> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let (seconds, fields) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => return Err(CronError::FieldCount(n)),
+        };
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS)?;
+        // Both 0 and 7 are Sunday
+        if has(weekdays, 7) {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Cron {
+            seconds: parse_field(seconds, 0, 59, &[])?,
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTHS)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl Cron {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first matching wall clock time strictly after `time`
+    pub fn next_naive(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut t = time.with_nanosecond(0).unwrap() + Duration::seconds(1);
+        let limit = time.year() + MAX_YEARS;
+        while t.year() <= limit {
+            let date = t.date();
+            if !has(self.months, t.month()) {
+                let (y, m) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd(y, m, 1).and_hms(0, 0, 0);
+            } else if !self.day_matches(date) {
+                t = date.succ().and_hms(0, 0, 0);
+            } else if !has(self.hours, t.hour()) {
+                t = date.and_hms(t.hour(), 0, 0) + Duration::hours(1);
+            } else if !has(self.minutes, t.minute()) {
+                t = date.and_hms(t.hour(), t.minute(), 0) + Duration::minutes(1);
+            } else if !has(self.seconds, t.second()) {
+                t = t + Duration::seconds(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+
+    /// The first matching time strictly after `time`, evaluated in local time. Wall clock times skipped by
+    /// DST changes are skipped as well
+    pub fn next_after<Tz: TimeZone>(&self, time: &chrono::DateTime<Tz>) -> Option<chrono::DateTime<LocalZone>> {
+        let mut naive = time.with_timezone(&LocalZone).naive_local();
+        loop {
+            naive = self.next_naive(naive)?;
+            if let Some(next) = LocalZone.from_local_datetime(&naive).earliest() {
+                return Some(next);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn naive(y: i32, m: u32, d: u32, h: u32, mi: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(y, m, d).and_hms(h, mi, s)
+    }
+
+    #[test]
+    fn test_parse() {
+        let cron: Cron = "0 9 * * MON-FRI".parse().unwrap();
+        assert_eq!(cron.seconds, 1);
+        assert_eq!(cron.minutes, 1);
+        assert_eq!(cron.hours, 1 << 9);
+        assert_eq!(cron.weekdays, 0b0111110);
+        let cron: Cron = "*/15 0 1,15 jan-mar,dec 7".parse().unwrap();
+        assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.days, 1 << 1 | 1 << 15);
+        assert_eq!(cron.months, 0b1_0000_0000_1110);
+        assert_eq!(cron.weekdays, 1);
+        let cron: Cron = "0 9 * * MON-SUN".parse().unwrap();
+        assert_eq!(cron.weekdays, 0b1111111);
+        assert_eq!("0 9 * * 5-7".parse::<Cron>().unwrap().weekdays, 0b1100001);
+        assert_eq!("* * * *".parse::<Cron>(), Err(CronError::FieldCount(4)));
+        assert_eq!("60 * * * *".parse::<Cron>(), Err(CronError::Range(60, "60".into())));
+        assert_eq!("* * * FOO *".parse::<Cron>(), Err(CronError::Field("FOO".into())));
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_next() {
+        let cron: Cron = "0 9 * * MON-FRI".parse().unwrap();
+        // Friday
+        let now = naive(2020, 12, 25, 12, 13, 14);
+        assert_eq!(cron.next_naive(now), Some(naive(2020, 12, 28, 9, 0, 0)));
+        assert_eq!(
+            cron.next_naive(naive(2020, 12, 28, 9, 0, 0)),
+            Some(naive(2020, 12, 29, 9, 0, 0))
+        );
+        let cron: Cron = "30 */20 * * * *".parse().unwrap();
+        assert_eq!(cron.next_naive(now), Some(naive(2020, 12, 25, 12, 20, 30)));
+        assert_eq!(
+            cron.next_naive(naive(2020, 12, 31, 23, 40, 30)),
+            Some(naive(2021, 1, 1, 0, 0, 30))
+        );
+        // Either the 13th or a Friday
+        let cron: Cron = "0 0 13 * FRI".parse().unwrap();
+        assert_eq!(cron.next_naive(now), Some(naive(2021, 1, 1, 0, 0, 0)));
+        assert_eq!(
+            cron.next_naive(naive(2021, 1, 9, 0, 0, 0)),
+            Some(naive(2021, 1, 13, 0, 0, 0))
+        );
+        // Steps on one day field still restrict days by the other one alone
+        let cron: Cron = "0 0 */2 * FRI".parse().unwrap();
+        assert_eq!(cron.next_naive(now), Some(naive(2021, 1, 1, 0, 0, 0)));
+        assert_eq!(
+            cron.next_naive(naive(2021, 1, 1, 0, 0, 0)),
+            Some(naive(2021, 1, 15, 0, 0, 0))
+        );
+        let cron: Cron = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(cron.next_naive(now), Some(naive(2024, 2, 29, 0, 0, 0)));
+        let cron: Cron = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(cron.next_naive(now), None);
+    }
+}
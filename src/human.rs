@@ -0,0 +1,310 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum HumanError {
+    #[error("Empty input")]
+    Empty,
+    #[error("Can't understand '{0}'")]
+    Unknown(String),
+    #[error("Missing number before '{0}'")]
+    MissingNumber(String),
+    #[error("Missing unit after '{0}'")]
+    MissingUnit(String),
+    #[error("Unknown duration unit '{0}'")]
+    Unit(String),
+    #[error("{0} doesn't exist in the timezone")]
+    Nonexistent(NaiveDateTime),
+    #[error("'{0}' is out of range")]
+    OutOfRange(String),
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Formats of local times without an offset, tried in order
+const LOCAL_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Longest duration in seconds, as durations are kept in milliseconds
+const MAX_SECS: i64 = i64::MAX / 1000;
+
+/// Length of a duration unit in seconds
+fn unit_secs(unit: &str) -> Option<i64> {
+    match unit {
+        "w" | "wk" | "wks" | "week" | "weeks" => Some(7 * 86400),
+        "d" | "day" | "days" => Some(86400),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(3600),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(60),
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(1),
+        _ => None,
+    }
+}
+
+/// Parse durations like `1h30m`, `2w` or `1 hour 30 minutes`
+pub fn parse_duration(s: &str) -> Result<Duration, HumanError> {
+    let s = s.trim().to_lowercase();
+    if s.is_empty() {
+        return Err(HumanError::Empty);
+    }
+    let mut total = 0;
+    let mut rest = s.as_str();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        rest = rest.strip_prefix("and ").unwrap_or(rest);
+        if rest.is_empty() {
+            break;
+        }
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or_else(|| rest.len());
+        if digits == 0 {
+            return Err(HumanError::MissingNumber(rest.into()));
+        }
+        let (num, after) = rest.split_at(digits);
+        let after = after.trim_start();
+        let letters = after.find(|c: char| !c.is_alphabetic()).unwrap_or_else(|| after.len());
+        if letters == 0 {
+            return Err(HumanError::MissingUnit(num.into()));
+        }
+        let (unit, after) = after.split_at(letters);
+        let secs = unit_secs(unit).ok_or_else(|| HumanError::Unit(unit.into()))?;
+        let num: i64 = num.parse().map_err(|_| HumanError::OutOfRange(s.clone()))?;
+        total = num
+            .checked_mul(secs)
+            .and_then(|secs| secs.checked_add(total))
+            .filter(|&total| total <= MAX_SECS)
+            .ok_or_else(|| HumanError::OutOfRange(s.clone()))?;
+        rest = after;
+    }
+    Ok(Duration::seconds(total))
+}
+
+fn weekday(word: &str) -> Option<Weekday> {
+    if word.len() < 3 {
+        return None;
+    }
+    let i = WEEKDAYS.iter().position(|d| d.starts_with(word))?;
+    Some(
+        [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ][i],
+    )
+}
+
+/// The first `day` after `today`, or from `today` on if `include_today`
+fn next_weekday(today: NaiveDate, day: Weekday, include_today: bool) -> NaiveDate {
+    let ahead = (day.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+    let ahead = if ahead == 0 && !include_today { 7 } else { ahead };
+    today + Duration::days(ahead as i64)
+}
+
+fn parse_date(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match word {
+        "today" => Some(today),
+        "tomorrow" => Some(today.succ()),
+        "yesterday" => Some(today.pred()),
+        _ => weekday(word)
+            .map(|d| next_weekday(today, d, true))
+            .or_else(|| NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()),
+    }
+}
+
+/// Parse a time of day like `17:30`, `9am`, `9:30 pm` or `noon`, with the number of words used
+fn parse_time(word: &str, next: Option<&str>) -> Option<(NaiveTime, usize)> {
+    match word {
+        "noon" => return Some((NaiveTime::from_hms(12, 0, 0), 1)),
+        "midnight" => return Some((NaiveTime::from_hms(0, 0, 0), 1)),
+        _ => (),
+    }
+    let (clock, pm, used) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(false), 1)
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(true), 1)
+    } else {
+        match next {
+            Some("am") => (word, Some(false), 2),
+            Some("pm") => (word, Some(true), 2),
+            _ => (word, None, 1),
+        }
+    };
+    // A bare number isn't a time
+    if pm.is_none() && !clock.contains(':') {
+        return None;
+    }
+    let mut parts = clock.split(':');
+    let hour: u32 = parts.next()?.parse().ok()?;
+    let min: u32 = parts.next().map_or(Some(0), |m| m.parse().ok())?;
+    let sec: u32 = parts.next().map_or(Some(0), |s| s.parse().ok())?;
+    if parts.next().is_some() {
+        return None;
+    }
+    let hour = match pm {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, min, sec).map(|t| (t, used))
+}
+
+fn resolve<Tz: TimeZone>(naive: NaiveDateTime, now: &DateTime<Tz>) -> Result<DateTime<Tz>, HumanError> {
+    now.timezone()
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or(HumanError::Nonexistent(naive))
+}
+
+/// Parse a time relative to `now`, in the timezone of `now`. Understands ISO 8601 (`2020-12-25T09:00:00+01:00`,
+/// or local without the offset), `now`, `in 3h`, `2 days ago`, and a date and/or a time of day, where the date is
+/// `today`, `tomorrow`, `yesterday`, `2020-12-25` or a weekday (`fri` for the coming one, `next fri` for the
+/// one after today), and the time is like `17:30`, `9am` or `noon`. Dates alone are at midnight, times alone
+/// are today
+pub fn parse_datetime<Tz: TimeZone>(s: &str, now: &DateTime<Tz>) -> Result<DateTime<Tz>, HumanError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(HumanError::Empty);
+    }
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&now.timezone()));
+    }
+    for format in &LOCAL_FORMATS {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(s, format) {
+            return resolve(naive, now);
+        }
+    }
+    let lower = s.to_lowercase();
+    if lower == "now" {
+        return Ok(now.clone());
+    }
+    if let Some(dur) = lower.strip_prefix("in ") {
+        return now
+            .clone()
+            .checked_add_signed(parse_duration(dur)?)
+            .ok_or_else(|| HumanError::OutOfRange(s.into()));
+    }
+    if let Some(dur) = lower.strip_suffix(" ago") {
+        return now
+            .clone()
+            .checked_sub_signed(parse_duration(dur)?)
+            .ok_or_else(|| HumanError::OutOfRange(s.into()));
+    }
+
+    let unknown = || HumanError::Unknown(s.into());
+    let today = now.naive_local().date();
+    let words = lower
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|w| !w.is_empty() && *w != "at" && *w != "on")
+        .collect::<Vec<_>>();
+    let (mut date, mut time) = (None, None);
+    let mut i = 0;
+    while i < words.len() {
+        let (word, next) = (words[i], words.get(i + 1).copied());
+        if word == "next" || word == "this" {
+            let day = next.and_then(weekday).ok_or_else(unknown)?;
+            if date.replace(next_weekday(today, day, word == "this")).is_some() {
+                return Err(unknown());
+            }
+            i += 2;
+        } else if let Some(d) = parse_date(word, today) {
+            if date.replace(d).is_some() {
+                return Err(unknown());
+            }
+            i += 1;
+        } else if let Some((t, used)) = parse_time(word, next) {
+            if time.replace(t).is_some() {
+                return Err(unknown());
+            }
+            i += used;
+        } else {
+            return Err(unknown());
+        }
+    }
+    if date.is_none() && time.is_none() {
+        return Err(unknown());
+    }
+    let naive = date
+        .unwrap_or(today)
+        .and_time(time.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0)));
+    resolve(naive, now)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{FixedOffset, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::minutes(90)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::weeks(2)));
+        assert_eq!(parse_duration("1 hour, 30 minutes"), Ok(Duration::minutes(90)));
+        assert_eq!(parse_duration("2 days and 3h"), Ok(Duration::hours(51)));
+        assert_eq!(parse_duration("5"), Err(HumanError::MissingUnit("5".into())));
+        assert_eq!(parse_duration("h"), Err(HumanError::MissingNumber("h".into())));
+        assert_eq!(parse_duration("3x"), Err(HumanError::Unit("x".into())));
+        assert_eq!(parse_duration(" "), Err(HumanError::Empty));
+        assert_eq!(
+            parse_duration("9999999999999999s"),
+            Err(HumanError::OutOfRange("9999999999999999s".into()))
+        );
+        assert!(parse_duration("99999999999w").is_err());
+        assert!(parse_duration("99999999999999999999999s").is_err());
+        assert_eq!(
+            parse_duration(&format!("{}s", MAX_SECS)),
+            Ok(Duration::seconds(MAX_SECS))
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        // A Wednesday
+        let now = Utc.ymd(2021, 3, 10).and_hms(15, 0, 0);
+        let at = |d, h, m| Ok(Utc.ymd(2021, 3, d).and_hms(h, m, 0));
+        assert_eq!(parse_datetime("tomorrow 9am", &now), at(11, 9, 0));
+        assert_eq!(parse_datetime("next fri", &now), at(12, 0, 0));
+        assert_eq!(parse_datetime("friday", &now), at(12, 0, 0));
+        assert_eq!(parse_datetime("wed", &now), at(10, 0, 0));
+        assert_eq!(parse_datetime("next wed", &now), at(17, 0, 0));
+        assert_eq!(parse_datetime("9:30 pm on Friday", &now), at(12, 21, 30));
+        assert_eq!(parse_datetime("in 3h", &now), at(10, 18, 0));
+        assert_eq!(parse_datetime("2 days ago", &now), at(8, 15, 0));
+        assert_eq!(parse_datetime("17:30", &now), at(10, 17, 30));
+        assert_eq!(parse_datetime("12am", &now), at(10, 0, 0));
+        assert_eq!(parse_datetime("noon", &now), at(10, 12, 0));
+        assert_eq!(
+            parse_datetime("2020-12-25", &now),
+            Ok(Utc.ymd(2020, 12, 25).and_hms(0, 0, 0))
+        );
+        assert_eq!(parse_datetime("2021-03-10T10:00:00+02:00", &now), at(10, 8, 0));
+        assert_eq!(parse_datetime("2021-03-10 10:00", &now), at(10, 10, 0));
+        assert!(parse_datetime("13pm", &now).is_err());
+        assert!(parse_datetime("today tomorrow", &now).is_err());
+        assert!(parse_datetime("someday", &now).is_err());
+        assert!(parse_datetime("in 9999999999w", &now).is_err());
+        assert!(parse_datetime("9999999999w ago", &now).is_err());
+
+        // Local forms are in the timezone of now
+        let tz = FixedOffset::east(3600);
+        let now = now.with_timezone(&tz);
+        assert_eq!(
+            parse_datetime("tomorrow 9am", &now),
+            Ok(tz.ymd(2021, 3, 11).and_hms(9, 0, 0))
+        );
+    }
+}
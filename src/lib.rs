@@ -0,0 +1,16 @@
+#[macro_use]
+extern crate gluon_codegen;
+#[macro_use]
+extern crate gluon;
+#[macro_use]
+extern crate serde_derive;
+
+pub mod clock;
+mod cron;
+pub mod human;
+pub mod repl;
+pub mod script;
+mod signal;
+pub mod storage;
+pub mod ui;
+pub mod util;
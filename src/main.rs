@@ -1,41 +1,179 @@
-#[macro_use]
-extern crate gluon_codegen;
-#[macro_use]
-extern crate gluon;
-#[macro_use]
-extern crate serde_derive;
-
-mod repl;
-mod script;
-mod signal;
-mod storage;
-mod util;
-
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
 
-use clap::{App, Arg};
+use chrono::{Local, Utc};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use dirs::config_dir;
 
-use util::print_gluon_err;
+use sched::clock::{self, ShiftedClock};
+use sched::storage::{self, Backend, Codec, MemoryBackend, SledBackend, Storage};
+use sched::util::{copy_dir, print_gluon_err};
+use sched::{human, repl, script, ui};
+
+/// Make sched run from `now` on a copy of the database, so that previews don't touch the real data. Returns
+/// the directory of the copy
+fn preview_at(now: &str) -> Result<PathBuf, String> {
+    let now = human::parse_datetime(now, &Local::now()).map_err(|e| e.to_string())?;
+    let db = storage::db_path();
+    let copy = env::temp_dir().join(format!("sched-preview-{}", process::id()));
+    if db.is_dir() {
+        copy_dir(&db, &copy).map_err(|e| format!("Can't copy the database: {}", e))?;
+    }
+    clock::set(Arc::new(ShiftedClock::starting_at(now.with_timezone(&Utc))));
+    script::preview();
+    Ok(copy)
+}
+
+/// Copy the sled database into the SQLite database at `path`
+#[cfg(feature = "sqlite")]
+fn to_sqlite(path: &Path) -> Result<(), String> {
+    let sled = storage::SledBackend::open(&storage::db_path());
+    let mut sqlite = storage::SqliteBackend::open(path).map_err(|e| e.to_string())?;
+    let count = sqlite.import(&sled).map_err(|e| e.to_string())?;
+    println!("Copied {} records to {}", count, path.display());
+    Ok(())
+}
+
+/// Open the database the options point to, without migrating it
+fn open_backend(matches: &ArgMatches, preview: Option<&Path>) -> Result<Box<dyn Backend>, String> {
+    if matches.is_present("memory") {
+        return Ok(Box::new(MemoryBackend::new()));
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        if let Some(path) = matches.value_of("sqlite") {
+            let backend =
+                storage::SqliteBackend::open(Path::new(path)).map_err(|e| format!("Can't open {}: {}", path, e))?;
+            return Ok(Box::new(backend));
+        }
+    }
+    let path = preview.map_or_else(storage::db_path, Path::to_path_buf);
+    Ok(Box::new(SledBackend::open(&path)))
+}
+
+/// Bring the database to the current schema version, or show what would change on dry runs
+fn migrate(matches: &ArgMatches, dry_run: bool) -> Result<(), String> {
+    let mut backend = open_backend(matches, None)?;
+    let report = storage::migrate::upgrade(&mut *backend, dry_run).map_err(|e| e.to_string())?;
+    println!("{}", report);
+    Ok(())
+}
 
 fn main() {
     let config_dir = config_dir().unwrap().join("sched");
     if !config_dir.is_dir() {
         fs::create_dir_all(&config_dir).unwrap();
     }
-    let matches = App::new("sched")
+    let app = App::new("sched")
         .arg(Arg::with_name("init-file").required(false))
-        .get_matches();
+        .arg(
+            Arg::with_name("now")
+                .long("now")
+                .takes_value(true)
+                .help("Run as if it's this time, like 'tomorrow 9am', on a copy of the data"),
+        )
+        .arg(
+            Arg::with_name("memory")
+                .long("memory")
+                .conflicts_with("now")
+                .help("Start with an empty store that's thrown away on exit"),
+        )
+        .arg(
+            Arg::with_name("codec")
+                .long("codec")
+                .takes_value(true)
+                .possible_values(&["cbor", "json"])
+                .default_value("cbor")
+                .help("How a new database encodes its records. Existing ones keep theirs"),
+        )
+        .subcommand(SubCommand::with_name("tui").about("Full-screen interface"))
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Bring the database to the current schema version, backing it up first")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only show what would change"),
+                ),
+        );
+    #[cfg(feature = "sqlite")]
+    let app = app
+        .arg(
+            Arg::with_name("sqlite")
+                .long("sqlite")
+                .takes_value(true)
+                .conflicts_with_all(&["now", "memory"])
+                .help("Use the SQLite database at this path instead of the sled one"),
+        )
+        .subcommand(
+            SubCommand::with_name("to-sqlite")
+                .about("Copy the sled database to a SQLite database")
+                .arg(Arg::with_name("file").required(true)),
+        );
+    let matches = app.get_matches();
+    #[cfg(feature = "sqlite")]
+    {
+        if let Some(m) = matches.subcommand_matches("to-sqlite") {
+            if let Err(e) = to_sqlite(Path::new(m.value_of("file").unwrap())) {
+                eprintln!("{}", e);
+            }
+            return;
+        }
+    }
+    if let Some(m) = matches.subcommand_matches("migrate") {
+        if let Err(e) = migrate(&matches, m.is_present("dry-run")) {
+            eprintln!("{}", e);
+        }
+        return;
+    }
     let init_file: PathBuf = matches
         .value_of("init-file")
         .map_or_else(|| config_dir.join("init.glu"), |s| s.into());
-    let vm = script::get_vm(config_dir);
-    if let Err(e) = script::run_user(&vm, &init_file) {
+    let preview = match matches.value_of("now").map(preview_at).transpose() {
+        Ok(preview) => preview,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let codec = Codec::from_name(matches.value_of("codec").unwrap()).unwrap();
+    let storage = match open_backend(&matches, preview.as_deref())
+        .and_then(|backend| Storage::new(backend, clock::get(), codec).map_err(|e| e.to_string()))
+    {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    run(
+        config_dir,
+        storage,
+        &init_file,
+        matches.subcommand_matches("tui").is_some(),
+    );
+    if let Some(preview) = preview {
+        let _ = fs::remove_dir_all(preview);
+    }
+}
+
+fn run(config_dir: PathBuf, storage: Storage, init_file: &Path, tui: bool) {
+    let vm = script::get_vm(config_dir, storage);
+    if let Err(e) = script::run_user(&vm, init_file) {
         print_gluon_err(e);
         return;
     }
-    if script::cmd::cmd_repl() {
+    script::job::rearm(&vm);
+    script::reminder::init();
+    script::overdue::init();
+    if tui {
+        if let Err(e) = ui::run() {
+            eprintln!("{}", e);
+        }
+    } else if script::cmd::cmd_repl() {
         let res = repl::run(&vm, "> ");
         if let Err(e) = res {
             print_gluon_err(e);
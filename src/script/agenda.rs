@@ -0,0 +1,350 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use gluon::{vm::ExternModule, Thread};
+
+use crate::{
+    script::{
+        sched::lock_store,
+        task::{EventTime, Task},
+        time::{Date, DateTime, Duration},
+    },
+    storage::{grace, is_closed, Interval, Result as StorageResult, Storage},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum ItemKind {
+    Event,
+    AllDay,
+    /// A daughter task, placed at its deadline
+    Task,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum Status {
+    Upcoming,
+    /// Running events, and the current daughter tasks as given by `find_current`
+    Current,
+    /// Unfinished daughter tasks past their deadlines and grace periods
+    Overdue,
+    Done,
+}
+
+#[derive(Clone, Debug, VmType, Pushable, Getable)]
+pub struct Item {
+    pub kind: ItemKind,
+    /// Id of the event or mother task
+    pub id: u32,
+    /// Id of the daughter task
+    pub log: Option<u32>,
+    pub name: String,
+    /// Start of the event occurrence or deadline of the task. Local midnight for all-day events
+    pub time: DateTime,
+    pub end: Option<DateTime>,
+    pub status: Status,
+}
+
+#[derive(Clone, Debug, VmType, Pushable, Getable)]
+pub struct Day {
+    pub date: Date,
+    /// All-day events first, then the rest by time
+    pub items: Vec<Item>,
+}
+
+/// Parse `today`, `tomorrow`, `week` (the next 7 days), a duration from now like `3d`, a date `YYYY-MM-DD`
+/// or an inclusive range of dates `YYYY-MM-DD..YYYY-MM-DD`
+pub fn parse_range(s: &str, now: DateTime) -> Result<Interval, String> {
+    let today = Date::of(&now).0;
+    let days = |from: NaiveDate, to: NaiveDate| Interval {
+        start: Date(from).start(),
+        end: Date(to.succ()).start(),
+    };
+    let date = |s: &str| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").map_err(|e| format!("{}: {}", s, e));
+    match s.trim() {
+        "today" => Ok(days(today, today)),
+        "tomorrow" => Ok(days(today.succ(), today.succ())),
+        "week" => Ok(days(today, today + chrono::Duration::days(6))),
+        s if s.contains("..") => {
+            let i = s.find("..").unwrap();
+            let (from, to) = (date(&s[..i])?, date(&s[i + 2..])?);
+            if from > to {
+                return Err(format!("{} is after {}", from, to));
+            }
+            Ok(days(from, to))
+        }
+        s => date(s).map(|d| days(d, d)).or_else(|_| {
+            let dur = Duration::parse(s)?;
+            Ok(Interval {
+                start: Date(today).start(),
+                end: DateTime(now.0 + dur.0),
+            })
+        }),
+    }
+}
+
+fn parse_range_now(s: &str) -> Result<Interval, String> {
+    let now = lock_store().map_err(|e| e.to_string())?.now();
+    parse_range(s, now.into())
+}
+
+fn event_status(interval: &Interval, now: DateTime) -> Status {
+    if now < interval.start {
+        Status::Upcoming
+    } else if now < interval.end {
+        Status::Current
+    } else {
+        Status::Done
+    }
+}
+
+fn event_items(store: &mut Storage, range: &Interval, now: DateTime) -> StorageResult<Vec<Item>> {
+    let ids = store
+        .find_obj(|o| o.typ == "event", Some(usize::MAX))
+        .into_iter()
+        .map(|o| o.id)
+        .collect::<Vec<_>>();
+    let mut items = Vec::new();
+    for id in ids {
+        let event = store.get_event(id)?;
+        for interval in event.occurrences(range.start, range.end) {
+            let status = event_status(&interval, now);
+            match event.time {
+                EventTime::Timed(..) => items.push(Item {
+                    kind: ItemKind::Event,
+                    id,
+                    log: None,
+                    name: event.object.name.clone(),
+                    time: interval.start,
+                    end: Some(interval.end),
+                    status,
+                }),
+                // Multi-day events show up on every day they span
+                EventTime::AllDay(_) => {
+                    let mut date = Date::of(&interval.start);
+                    while date.start() < interval.end {
+                        let start = date.start();
+                        if range.start < Date(date.0.succ()).start() && start < range.end {
+                            items.push(Item {
+                                kind: ItemKind::AllDay,
+                                id,
+                                log: None,
+                                name: event.object.name.clone(),
+                                time: start,
+                                end: Some(interval.end),
+                                status,
+                            });
+                        }
+                        date = Date(date.0.succ());
+                    }
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Daughter tasks with deadlines in the range, and overdue ones from before it when the range contains now
+fn task_items(store: &mut Storage, range: &Interval, now: DateTime) -> StorageResult<Vec<Item>> {
+    let includes_now = range.start <= now && now < range.end;
+    let logs = store.find_log(|l| l.typ == "task.task", Some(usize::MAX));
+    let mut tasks: HashMap<u32, (Task, Option<u32>)> = HashMap::new();
+    let mut items = Vec::new();
+    for log in logs {
+        let (task_id, deadline) = match (
+            log.attrs.get("task-id").and_then(|v| v.as_u64()),
+            log.attrs.get("deadline").and_then(|v| v.as_i64()),
+        ) {
+            (Some(task), Some(deadline)) => (task as u32, DateTime::from(Utc.timestamp(deadline, 0))),
+            _ => continue,
+        };
+        let finished = is_closed(&log.attrs);
+        let in_range = range.start <= deadline && deadline < range.end;
+        if !in_range && (finished || !includes_now || deadline >= range.start) {
+            continue;
+        }
+        if !tasks.contains_key(&task_id) {
+            let task = store.get_task(task_id)?;
+            let current = store.find_current(task_id)?;
+            tasks.insert(task_id, (task, current));
+        }
+        let (task, current) = &tasks[&task_id];
+        let status = if finished {
+            Status::Done
+        } else if deadline.0 + grace(&task.object.attrs) < now.0 {
+            Status::Overdue
+        } else if *current == Some(log.id) {
+            Status::Current
+        } else {
+            Status::Upcoming
+        };
+        if !in_range && status != Status::Overdue {
+            continue;
+        }
+        items.push(Item {
+            kind: ItemKind::Task,
+            id: task_id,
+            log: Some(log.id),
+            name: task.object.name.clone(),
+            // Overdue tasks from before the range are shown on today
+            time: if in_range { deadline } else { now },
+            end: None,
+            status,
+        });
+    }
+    Ok(items)
+}
+
+pub fn agenda_with(store: &mut Storage, range: Interval, now: DateTime) -> StorageResult<Vec<Day>> {
+    let mut days: BTreeMap<Date, Vec<Item>> = BTreeMap::new();
+    let mut items = event_items(store, &range, now)?;
+    items.extend(task_items(store, &range, now)?);
+    for item in items {
+        days.entry(Date::of(&item.time)).or_insert_with(Vec::new).push(item);
+    }
+    Ok(days
+        .into_iter()
+        .map(|(date, mut items)| {
+            items.sort_by_key(|i| (i.kind != ItemKind::AllDay, i.time));
+            Day { date, items }
+        })
+        .collect())
+}
+
+/// Event occurrences and daughter tasks in `[from, to)`, grouped by local day
+pub fn agenda(from: DateTime, to: DateTime) -> StorageResult<Vec<Day>> {
+    let range = Interval { start: from, end: to };
+    let mut store = lock_store()?;
+    let now = store.now().into();
+    agenda_with(&mut store, range, now)
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            type ItemKind => ItemKind,
+            type Status => Status,
+            type Item => Item,
+            type Day => Day,
+            agenda => primitive!(2, agenda),
+            parse_range => primitive!(1, parse_range_now),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        clock::{FakeClock, LocalZone},
+        storage::{AllDay, Conflicts, Every, OptRepeated, Repeated, Stop},
+    };
+
+    fn local(d: u32, h: u32) -> DateTime {
+        LocalZone.ymd(2021, 3, d).and_hms(h, 0, 0).into()
+    }
+
+    /// A store at noon on March 10 with tasks and events around it
+    fn store() -> Storage {
+        let clock = Arc::new(FakeClock::new(local(10, 12).0.with_timezone(&Utc)));
+        let mut store = Storage::in_memory(clock);
+        let mut task = |name: &str, deadline: OptRepeated| store.create_task(name, "habit", deadline, 0, None).unwrap();
+        task("read", OptRepeated::Single(local(9, 9)));
+        let walk = task("walk", OptRepeated::Single(local(10, 8)));
+        task("water", OptRepeated::Single(local(10, 9)));
+        let daily = Repeated::new(
+            vec![local(10, 18)],
+            Every::Time(chrono::Duration::days(1).into()),
+            Stop::Nonstop,
+        );
+        task("cook", OptRepeated::Repeat(daily));
+        let walk = store.get_task(walk).unwrap().cache[0];
+        store.task_finish(walk, local(10, 8)).unwrap();
+
+        let mut event = |name: &str, start: DateTime, hours: i64| {
+            let duration = chrono::Duration::hours(hours).into();
+            store
+                .create_event(name, "", OptRepeated::Single(start), duration, None, Conflicts::Allow)
+                .unwrap();
+        };
+        event("standup", local(10, 10), 1);
+        event("lunch", local(10, 11), 2);
+        event("dinner", local(10, 19), 1);
+        let trip = AllDay {
+            date: Date(NaiveDate::from_ymd(2021, 3, 10)),
+            days: 2,
+            every: None,
+            until: None,
+        };
+        store.create_all_day_event("trip", "", trip, None).unwrap();
+        store
+    }
+
+    fn names(day: &Day) -> Vec<(&str, Status)> {
+        day.items.iter().map(|i| (i.name.as_str(), i.status)).collect()
+    }
+
+    #[test]
+    fn test_agenda() {
+        let mut store = store();
+        let now = local(10, 12);
+        let days = agenda_with(&mut store, parse_range("2021-03-10..2021-03-11", now).unwrap(), now).unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(
+            names(&days[0]),
+            vec![
+                ("trip", Status::Current),
+                ("walk", Status::Done),
+                ("water", Status::Overdue),
+                ("standup", Status::Done),
+                ("lunch", Status::Current),
+                // Overdue from before the range, so shown now
+                ("read", Status::Overdue),
+                ("cook", Status::Current),
+                ("dinner", Status::Upcoming),
+            ]
+        );
+        assert_eq!(days[0].items[5].time, now);
+        assert_eq!(
+            names(&days[1]),
+            vec![("trip", Status::Current), ("cook", Status::Upcoming)]
+        );
+
+        // Overdue tasks only show up on ranges including now
+        let tomorrow = parse_range("tomorrow", now).unwrap();
+        let items = task_items(&mut store, &tomorrow, now).unwrap();
+        assert_eq!(
+            items.iter().map(|i| (i.name.as_str(), i.status)).collect::<Vec<_>>(),
+            vec![("cook", Status::Upcoming)]
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let now = DateTime::from(chrono::Local.ymd(2021, 3, 10).and_hms(15, 0, 0));
+        let start = |y, m, d| Date(NaiveDate::from_ymd(y, m, d)).start();
+        let range = |from, to| Ok(Interval { start: from, end: to });
+        assert_eq!(parse_range("today", now), range(start(2021, 3, 10), start(2021, 3, 11)));
+        assert_eq!(
+            parse_range("tomorrow", now),
+            range(start(2021, 3, 11), start(2021, 3, 12))
+        );
+        assert_eq!(parse_range("week", now), range(start(2021, 3, 10), start(2021, 3, 17)));
+        assert_eq!(
+            parse_range("2021-02-27", now),
+            range(start(2021, 2, 27), start(2021, 2, 28))
+        );
+        assert_eq!(
+            parse_range("2021-02-27..2021-03-01", now),
+            range(start(2021, 2, 27), start(2021, 3, 2))
+        );
+        assert_eq!(
+            parse_range("2d", now),
+            range(start(2021, 3, 10), DateTime(now.0 + chrono::Duration::days(2)))
+        );
+        assert!(parse_range("2021-03-01..2021-02-01", now).is_err());
+        assert!(parse_range("someday", now).is_err());
+    }
+}
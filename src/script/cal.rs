@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, NaiveDate};
+use gluon::{vm::ExternModule, Thread};
+
+use crate::{
+    clock::LocalZone,
+    script::{
+        agenda::{self, Item, ItemKind, Status},
+        sched::lock_store,
+        time::{Date, DateTime},
+    },
+    storage::{Interval, Result as StorageResult},
+    util::fit,
+};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum View {
+    Month,
+    Week,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum SpanStyle {
+    Today,
+    Day,
+    /// Days outside of the month being shown
+    OtherMonth,
+    Blank,
+    Event,
+    AllDay,
+    Task,
+    Current,
+    Overdue,
+    Done,
+    /// The count of items which didn't fit in a cell
+    More,
+}
+
+/// A piece of a cell padded to the cell width
+#[derive(Clone, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+#[derive(Clone, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct Grid {
+    pub title: String,
+    /// Weekday names padded to the cell width
+    pub header: Vec<String>,
+    /// Lines of the grid, each with a span for every day of the week
+    pub rows: Vec<Vec<Span>>,
+}
+
+/// The first day shown and the number of weeks in the grid
+fn bounds(view: View, at: NaiveDate) -> (NaiveDate, u32) {
+    let monday = |d: NaiveDate| d - chrono::Duration::days(d.weekday().num_days_from_monday() as i64);
+    match view {
+        View::Week => (monday(at), 1),
+        View::Month => {
+            let first = at.with_day(1).unwrap();
+            let start = monday(first);
+            let next_month = match first.month() {
+                12 => NaiveDate::from_ymd(first.year() + 1, 1, 1),
+                m => NaiveDate::from_ymd(first.year(), m + 1, 1),
+            };
+            let days = next_month.signed_duration_since(start).num_days();
+            (start, ((days + 6) / 7) as u32)
+        }
+    }
+}
+
+fn item_span(item: &Item, width: usize) -> Span {
+    let text = match item.kind {
+        ItemKind::AllDay => item.name.clone(),
+        _ => format!(
+            "{} {}",
+            item.time.0.with_timezone(&LocalZone).format("%H:%M"),
+            item.name
+        ),
+    };
+    let style = match (item.status, item.kind) {
+        (Status::Overdue, _) => SpanStyle::Overdue,
+        (Status::Done, _) => SpanStyle::Done,
+        (Status::Current, _) => SpanStyle::Current,
+        (_, ItemKind::Event) => SpanStyle::Event,
+        (_, ItemKind::AllDay) => SpanStyle::AllDay,
+        (_, ItemKind::Task) => SpanStyle::Task,
+    };
+    Span {
+        text: fit(&text, width),
+        style,
+    }
+}
+
+/// Lay out the grid around `at`, with `lines` lines of items under the day number of each cell
+fn layout(
+    view: View,
+    at: NaiveDate,
+    today: NaiveDate,
+    items: &BTreeMap<NaiveDate, Vec<Item>>,
+    width: usize,
+    lines: usize,
+) -> Grid {
+    let (start, weeks) = bounds(view, at);
+    let title = match view {
+        View::Month => at.format("%B %Y").to_string(),
+        View::Week => format!("Week {} of {}", at.iso_week().week(), at.iso_week().year()),
+    };
+    let none = Vec::new();
+    let mut rows = Vec::new();
+    for week in 0..weeks {
+        let dates = (0..7)
+            .map(|d| start + chrono::Duration::days(week as i64 * 7 + d))
+            .collect::<Vec<_>>();
+        rows.push(
+            dates
+                .iter()
+                .map(|&date| Span {
+                    text: fit(&date.day().to_string(), width),
+                    style: if date == today {
+                        SpanStyle::Today
+                    } else if view == View::Month && date.month() != at.month() {
+                        SpanStyle::OtherMonth
+                    } else {
+                        SpanStyle::Day
+                    },
+                })
+                .collect(),
+        );
+        for line in 0..lines {
+            rows.push(
+                dates
+                    .iter()
+                    .map(|date| {
+                        let items = items.get(date).unwrap_or(&none);
+                        if line == lines - 1 && items.len() > lines {
+                            Span {
+                                text: fit(&format!("+{} more", items.len() - line), width),
+                                style: SpanStyle::More,
+                            }
+                        } else if let Some(item) = items.get(line) {
+                            item_span(item, width)
+                        } else {
+                            Span {
+                                text: fit("", width),
+                                style: SpanStyle::Blank,
+                            }
+                        }
+                    })
+                    .collect(),
+            );
+        }
+    }
+    Grid {
+        title,
+        header: WEEKDAYS.iter().map(|d| fit(d, width)).collect(),
+        rows,
+    }
+}
+
+/// The month or week containing `at`, with `width` characters and `lines` item lines per day
+pub fn grid(view: View, at: Date, width: u32, lines: u32) -> StorageResult<Grid> {
+    let (start, weeks) = bounds(view, at.0);
+    let range = Interval {
+        start: Date(start).start(),
+        end: Date(start + chrono::Duration::weeks(weeks as i64)).start(),
+    };
+    let mut store = lock_store()?;
+    let now = DateTime::from(store.now());
+    let items = agenda::agenda_with(&mut store, range, now)?
+        .into_iter()
+        .map(|day| (day.date.0, day.items))
+        .collect();
+    Ok(layout(
+        view,
+        at.0,
+        Date::of(&now).0,
+        &items,
+        width.max(1) as usize,
+        lines.max(1) as usize,
+    ))
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            type View => View,
+            type SpanStyle => SpanStyle,
+            type Span => Span,
+            type Grid => Grid,
+            grid => primitive!(4, grid),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn item(name: &str) -> Item {
+        Item {
+            kind: ItemKind::AllDay,
+            id: 1,
+            log: None,
+            name: name.into(),
+            time: chrono::Local.ymd(2021, 3, 10).and_hms(0, 0, 0).into(),
+            end: None,
+            status: Status::Upcoming,
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let date = |d| NaiveDate::from_ymd(2021, 3, d);
+        assert_eq!(bounds(View::Month, date(17)), (date(1), 5));
+        assert_eq!(bounds(View::Week, date(17)), (date(15), 1));
+        assert_eq!(bounds(View::Month, NaiveDate::from_ymd(2021, 5, 1)).1, 6);
+
+        let mut items = BTreeMap::new();
+        items.insert(date(10), vec![item("a"), item("b"), item("c")]);
+        let grid = layout(View::Month, date(17), date(11), &items, 6, 2);
+        assert_eq!(grid.title, "March 2021");
+        assert_eq!(grid.rows.len(), 5 * 3);
+        // The 10th is a Wednesday in the second week
+        assert_eq!(grid.rows[3][2].text, "10    ");
+        assert_eq!(grid.rows[3][3].style, SpanStyle::Today);
+        assert_eq!(grid.rows[4][2].text, "a     ");
+        assert_eq!(grid.rows[5][2].text, "+2 mo…");
+        assert_eq!(grid.rows[5][2].style, SpanStyle::More);
+        assert_eq!(grid.rows[12][6].style, SpanStyle::OtherMonth);
+        assert_eq!(grid.rows[12][6].text, "4     ");
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::Mutex;
+
+use chrono::Utc;
+use gluon::{vm::ExternModule, Thread};
+use lazy_static::lazy_static;
+
+use crate::{
+    attrs,
+    clock::LocalZone,
+    script::{
+        job,
+        sched::{lock_store, wait_store, Log},
+        time::{DateTime, Duration},
+    },
+    storage::{Error, Result as StorageResult, Storage},
+};
+
+/// The running focus session. There can only be one at a time
+struct Session {
+    /// Id of the `focus.start` log, which identifies the session
+    start_log: u32,
+    /// Id of the daughter task the session is tied to
+    task: u32,
+    started: chrono::DateTime<Utc>,
+    brk: Duration,
+}
+
+lazy_static! {
+    /// Always locked after the store, when both are needed
+    static ref SESSION: Mutex<Option<Session>> = Mutex::new(None);
+}
+
+#[derive(Clone, Debug, Default, VmType, Pushable, Getable)]
+pub struct FocusSummary {
+    pub sessions: u32,
+    /// Sessions stopped before the work interval ended
+    pub interrupted: u32,
+    pub worked: Duration,
+}
+
+impl FocusSummary {
+    fn from_logs<'a, I: Iterator<Item = &'a Log>>(logs: I) -> FocusSummary {
+        logs.fold(FocusSummary::default(), |mut sum, l| {
+            sum.sessions += 1;
+            if l.attrs.get("interrupted").and_then(|v| v.as_bool()).unwrap_or(false) {
+                sum.interrupted += 1;
+            }
+            let worked = l.attrs.get("worked").and_then(|v| v.as_i64()).unwrap_or(0);
+            sum.worked = Duration(sum.worked.0 + chrono::Duration::seconds(worked));
+            sum
+        })
+    }
+}
+
+/// Start a focus session on daughter task `task`, with a work interval followed by a break
+fn start(task: u32, work: Duration, brk: Duration) -> StorageResult<u32> {
+    let mut store = lock_store()?;
+    let mut session = SESSION.lock().unwrap();
+    if let Some(s) = session.as_ref() {
+        return Err(Error::FocusActive(s.task));
+    }
+    if store.get_log(task)?.typ != "task.task" {
+        return Err(Error::LogNotTask(task));
+    }
+    let start_log = store.create_log(
+        "focus.start".into(),
+        attrs! { "task": task, "work": work.num_seconds(), "break": brk.num_seconds() },
+    )?;
+    let started = store.now();
+    *session = Some(Session {
+        start_log,
+        task,
+        started,
+        brk,
+    });
+    job::at(&job::JOBS, started + work.0, "focus.end", move || {
+        if let Err(e) = end(&mut wait_store(), start_log, false) {
+            eprintln!("Error ending focus session: {}", e);
+        }
+    });
+    Ok(start_log)
+}
+
+/// End the session started by `start_log`, if it's still running. Breaks are only taken after a full
+/// work interval
+fn end(store: &mut Storage, start_log: u32, interrupted: bool) -> StorageResult<()> {
+    let session = {
+        let mut session = SESSION.lock().unwrap();
+        match session.as_ref() {
+            Some(s) if s.start_log == start_log => session.take().unwrap(),
+            _ => return Ok(()),
+        }
+    };
+    let now = store.now();
+    let worked = now - session.started;
+    store.create_log(
+        "focus.end".into(),
+        attrs! {
+            "task": session.task,
+            "start": start_log,
+            "worked": worked.num_seconds(),
+            "interrupted": interrupted,
+        },
+    )?;
+    if !interrupted && session.brk.num_seconds() > 0 {
+        let task = session.task;
+        job::at(&job::JOBS, now + session.brk.0, "focus.break_end", move || {
+            let res = wait_store().create_log("focus.break_end".into(), attrs! { "task": task, "start": start_log });
+            if let Err(e) = res {
+                eprintln!("Error ending focus break: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn stop(_: ()) -> StorageResult<()> {
+    let mut store = lock_store()?;
+    let start_log = SESSION.lock().unwrap().as_ref().map(|s| s.start_log);
+    end(&mut store, start_log.ok_or(Error::NoFocus)?, true)
+}
+
+/// The daughter task of the running session, if any
+fn current(_: ()) -> Option<u32> {
+    SESSION.lock().unwrap().as_ref().map(|s| s.task)
+}
+
+fn focus_ends(store: &mut Storage) -> Vec<Log> {
+    store.find_log(|l| l.typ == "focus.end", Some(usize::MAX))
+}
+
+fn summary_task(task: u32) -> StorageResult<FocusSummary> {
+    let logs = focus_ends(&mut *lock_store()?);
+    Ok(FocusSummary::from_logs(logs.iter().filter(|l| {
+        l.attrs.get("task").and_then(|v| v.as_u64()) == Some(task as u64)
+    })))
+}
+
+/// Summary of the sessions ended on the same local day as `day`
+fn summary_day(day: DateTime) -> StorageResult<FocusSummary> {
+    let day = day.0.with_timezone(&LocalZone).date();
+    let logs = focus_ends(&mut *lock_store()?);
+    Ok(FocusSummary::from_logs(
+        logs.iter().filter(|l| l.time.0.with_timezone(&LocalZone).date() == day),
+    ))
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            type FocusSummary => FocusSummary,
+            start => primitive!(3, start),
+            stop => primitive!(1, stop),
+            current => primitive!(1, current),
+            summary_task => primitive!(1, summary_task),
+            summary_day => primitive!(1, summary_day),
+        },
+    )
+}
@@ -1,21 +1,70 @@
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration as StdDuration;
 
 use chrono::{DateTime, Duration, NaiveDateTime, Offset, Utc};
 use gluon::{
     vm::{
-        api::{OwnedFunction, IO},
+        api::{OwnedFunction, WithVM, IO},
         ExternModule,
     },
-    Thread,
+    Thread, ThreadExt,
 };
 use lazy_static::lazy_static;
+use tokio::{runtime::Builder, sync::Notify, time::delay_for};
 
-use crate::script::time::{DateTime as GluonDateTime, Duration as GluonDuration};
+use crate::clock::{self, Clock};
+use crate::cron::{Cron, CronError};
+use crate::script::{
+    sched::{lock_store, wait_store, AttrValue},
+    time::{DateTime as GluonDateTime, Duration as GluonDuration},
+};
+use crate::storage::{Error, OptRepeated, Result as StorageResult};
 use crate::util::print_gluon_err;
 
 type TimedFunc = OwnedFunction<fn(GluonDateTime) -> IO<()>>;
 type CountedFunc = OwnedFunction<fn(u32) -> IO<()>>;
 type NextTimeFunc = OwnedFunction<fn(GluonDateTime) -> Option<GluonDateTime>>;
+type StoredFunc = OwnedFunction<fn(AttrValue) -> IO<()>>;
+
+/// What to do with the occurrences of a stored job that were missed while sched wasn't running
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, VmType, Pushable, Getable)]
+pub enum Missed {
+    /// Run the job once for all the missed occurrences
+    Once,
+    /// Run the job for every missed occurrence, up to `MAX_CATCH_UP` times
+    All,
+    Skip,
+}
+
+/// Definition of a job that is persisted across restarts. As Gluon functions can't be stored, the job
+/// refers to a global function of type `Value -> IO ()` by its path (e.g. `reminders.water`)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JobDef {
+    pub func: String,
+    pub args: AttrValue,
+    pub schedule: OptRepeated,
+    /// The next time the job is due
+    pub next: GluonDateTime,
+    pub missed: Missed,
+}
+
+impl JobDef {
+    /// Move on to the next occurrence. Returns `false` when there's none left
+    fn advance(&mut self) -> bool {
+        match self.schedule {
+            OptRepeated::Single(_) => false,
+            OptRepeated::Repeat(ref mut repeat) => match repeat.next() {
+                Some(next) => {
+                    self.next = next;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
 
 // `start` and `stop` are always UTC time
 enum Job {
@@ -36,164 +85,681 @@ enum Job {
         next: NextTimeFunc,
         job: TimedFunc,
     },
+    Cron {
+        start: NaiveDateTime,
+        cron: Cron,
+        job: TimedFunc,
+    },
+    /// A job that is persisted in the storage under `key`
+    Stored {
+        start: NaiveDateTime,
+        key: u32,
+        def: JobDef,
+        job: StoredFunc,
+    },
+    /// A one-shot job defined on the Rust side
+    Native {
+        start: NaiveDateTime,
+        desc: String,
+        job: Box<dyn FnOnce() + Send>,
+    },
+}
+
+impl Job {
+    fn start(&self) -> NaiveDateTime {
+        match self {
+            Job::Counted { start, .. }
+            | Job::Until { start, .. }
+            | Job::Custom { start, .. }
+            | Job::Cron { start, .. }
+            | Job::Stored { start, .. }
+            | Job::Native { start, .. } => *start,
+        }
+    }
+
+    fn info(&self, id: u32) -> JobInfo {
+        let (kind, remaining) = match self {
+            Job::Until { .. } => ("until".to_string(), None),
+            Job::Counted { count, .. } => ("counted".to_string(), Some(*count)),
+            Job::Custom { .. } => ("custom".to_string(), None),
+            Job::Cron { .. } => ("cron".to_string(), None),
+            Job::Stored { def, .. } => (def.func.clone(), None),
+            Job::Native { desc, .. } => (desc.clone(), Some(1)),
+        };
+        JobInfo {
+            id,
+            kind,
+            next: GluonDateTime(DateTime::from_utc(self.start(), Utc.fix())),
+            remaining,
+        }
+    }
+}
+
+#[derive(Clone, Debug, VmType, Pushable, Getable)]
+pub struct JobInfo {
+    pub id: u32,
+    pub kind: String,
+    /// The next time the job will run
+    pub next: GluonDateTime,
+    /// Number of runs left, if it's known
+    pub remaining: Option<u32>,
+}
+
+struct Queue {
+    next_id: u32,
+    jobs: Vec<(u32, Job)>,
+    /// Ids of the jobs being run, which are out of `jobs` meanwhile
+    running: Vec<u32>,
+    /// Ids of the running jobs cancelled meanwhile, so that they aren't put back
+    cancelled: Vec<u32>,
+}
+
+/// A queue of jobs, the runner's wake-up signal and the clock the jobs run by. Scripts schedule on `JOBS`,
+/// tests on their own queues
+pub struct Jobs {
+    queue: Mutex<Queue>,
+    /// Wakes the runner up when the set of jobs changes
+    wake: Notify,
+    clock: Arc<dyn Clock>,
+    /// Whether the jobs defined by scripts are only printed, as they can reach outside of sched
+    preview: AtomicBool,
+}
+
+impl Jobs {
+    pub fn new(clock: Arc<dyn Clock>) -> Jobs {
+        Jobs {
+            queue: Mutex::new(Queue {
+                next_id: 1,
+                jobs: Vec::new(),
+                running: Vec::new(),
+                cancelled: Vec::new(),
+            }),
+            wake: Notify::new(),
+            clock,
+            preview: AtomicBool::new(false),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub fn set_preview(&self) {
+        self.preview.store(true, Ordering::SeqCst);
+    }
+
+    fn previewing(&self) -> bool {
+        self.preview.load(Ordering::SeqCst)
+    }
 }
 
 lazy_static! {
-    static ref JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
-}
-
-pub fn run() {
-    let mut jobs = JOBS.lock().unwrap();
-    *jobs = jobs
-        .drain(..)
-        .filter_map(|j| {
-            let now = Utc::now().naive_utc();
-            let next = match j {
-                Job::Counted { start, .. } | Job::Until { start, .. } | Job::Custom { start, .. } => start,
-            };
-            if next > now {
-                return Some(j);
+    /// Runs by the clock of sched, which is set before the runner starts
+    pub static ref JOBS: Jobs = Jobs::new(clock::get());
+}
+
+/// Most runs of a stored job with `Missed::All` at startup, so that a long time away doesn't run a frequent job
+/// for hours
+const MAX_CATCH_UP: u32 = 100;
+
+/// Longest time the runner sleeps in one go. The tokio timer is monotonic, so a suspended machine would
+/// otherwise wake up late
+const MAX_SLEEP: StdDuration = StdDuration::from_secs(60);
+
+/// Schedule on the global queue
+fn schedule(job: Job) -> u32 {
+    push(&JOBS, job)
+}
+
+fn push(jobs: &Jobs, job: Job) -> u32 {
+    let id = {
+        let mut queue = jobs.queue.lock().unwrap();
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.jobs.push((id, job));
+        id
+    };
+    jobs.wake.notify();
+    id
+}
+
+/// Put back the jobs that have run, except those cancelled while running
+fn push_all(jobs: &Jobs, ran: &[u32], rearmed: Vec<(u32, Job)>) {
+    let cancelled = {
+        let mut queue = jobs.queue.lock().unwrap();
+        queue.running.retain(|id| !ran.contains(id));
+        let (cancelled, rearmed): (Vec<_>, Vec<_>) =
+            rearmed.into_iter().partition(|(id, _)| queue.cancelled.contains(id));
+        queue.cancelled.retain(|id| !ran.contains(id));
+        if !rearmed.is_empty() {
+            queue.jobs.extend(rearmed);
+            jobs.wake.notify();
+        }
+        cancelled
+    };
+    // Stored jobs have been saved again after running
+    for (_, job) in cancelled {
+        if let Job::Stored { key, .. } = job {
+            if let Err(e) = wait_store().del_job(key) {
+                eprintln!("Error removing job {}: {}", key, e);
             }
-            match j {
-                Job::Counted {
+        }
+    }
+}
+
+/// Run the jobs of `jobs` due by its clock
+pub fn run(jobs: &Jobs) {
+    let now = jobs.now().naive_utc();
+    // Take the due jobs out first, so that jobs are free to schedule new jobs while running
+    let due = {
+        let mut queue = jobs.queue.lock().unwrap();
+        let (due, pending) = queue.jobs.drain(..).partition::<Vec<_>, _>(|(_, j)| j.start() <= now);
+        queue.jobs = pending;
+        let ids = due.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        queue.running.extend(ids);
+        due
+    };
+    let ran = due.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    push_all(
+        jobs,
+        &ran,
+        due.into_iter()
+            .filter_map(|(id, j)| run_job(jobs, id, j, now).map(|j| (id, j)))
+            .collect(),
+    );
+}
+
+/// Sleep until the earliest job of `jobs` is due by its clock, run it, and repeat
+pub async fn run_loop(jobs: &Jobs) {
+    loop {
+        let earliest = jobs.queue.lock().unwrap().jobs.iter().map(|(_, j)| j.start()).min();
+        match earliest {
+            Some(earliest) => {
+                let wait = (earliest - jobs.now().naive_utc())
+                    .to_std()
+                    .unwrap_or(StdDuration::from_secs(0))
+                    .min(MAX_SLEEP);
+                tokio::select! {
+                    _ = delay_for(wait) => run(jobs),
+                    _ = jobs.wake.notified() => (),
+                }
+            }
+            None => jobs.wake.notified().await,
+        }
+    }
+}
+
+/// Runs a due job, and returns the job for its next run if there's any
+fn run_job(jobs: &Jobs, id: u32, j: Job, now: NaiveDateTime) -> Option<Job> {
+    let next = j.start();
+    let skip = jobs.previewing() && !matches!(j, Job::Native { .. });
+    if skip {
+        eprintln!("Preview: not running the {} job due at {}", j.info(id).kind, next);
+    }
+    match j {
+        Job::Counted {
+            interval,
+            count,
+            mut job,
+            ..
+        } => {
+            let count = count - 1;
+            if !skip {
+                if let Err(e) = job.call(count) {
+                    eprintln!("Error running job handler:");
+                    print_gluon_err(e.into());
+                }
+            }
+            if count > 0 {
+                Some(Job::Counted {
+                    start: next + interval,
                     interval,
                     count,
-                    mut job,
-                    ..
-                } => {
-                    let count = count - 1;
-                    if let Err(e) = job.call(count) {
-                        eprintln!("Error running job handler:");
-                        print_gluon_err(e.into());
-                    }
-                    if count > 0 {
-                        Some(Job::Counted {
-                            start: next + interval,
-                            interval,
-                            count,
-                            job,
-                        })
-                    } else {
-                        None
-                    }
+                    job,
+                })
+            } else {
+                None
+            }
+        }
+        Job::Until {
+            start,
+            interval,
+            stop,
+            mut job,
+        } => {
+            let next = start + interval;
+            if !skip {
+                if let Err(e) = job.call(GluonDateTime(DateTime::from_utc(now, Utc.fix()))) {
+                    eprintln!("Error running job handler:");
+                    print_gluon_err(e.into());
                 }
-                Job::Until {
-                    start,
+            }
+            if next < stop {
+                Some(Job::Until {
+                    start: next,
                     interval,
                     stop,
-                    mut job,
-                } => {
-                    let next = start + interval;
-                    if let Err(e) = job.call(GluonDateTime(DateTime::from_utc(now, Utc.fix()))) {
-                        eprintln!("Error running job handler:");
-                        print_gluon_err(e.into());
-                    }
-                    if next < stop {
-                        Some(Job::Until {
-                            start: next,
-                            interval,
-                            stop,
-                            job,
-                        })
-                    } else {
-                        None
-                    }
+                    job,
+                })
+            } else {
+                None
+            }
+        }
+        Job::Custom { mut next, mut job, .. } => {
+            let now = GluonDateTime(DateTime::from_utc(now, Utc.fix()));
+            if !skip {
+                if let Err(e) = job.call(now) {
+                    eprintln!("Error running job handler:");
+                    print_gluon_err(e.into());
                 }
-                Job::Custom { mut next, mut job, .. } => {
-                    let now = GluonDateTime(DateTime::from_utc(now, Utc.fix()));
-                    if let Err(e) = job.call(now) {
-                        eprintln!("Error running job handler:");
-                        print_gluon_err(e.into());
-                    }
-                    if let Some(time) = next.call(now).unwrap() {
-                        Some(Job::Custom {
-                            start: time.0.naive_utc(),
-                            next,
-                            job,
-                        })
-                    } else {
-                        None
-                    }
+            }
+            if let Some(time) = next.call(now).unwrap() {
+                Some(Job::Custom {
+                    start: time.0.naive_utc(),
+                    next,
+                    job,
+                })
+            } else {
+                None
+            }
+        }
+        Job::Cron { cron, mut job, .. } => {
+            if !skip {
+                if let Err(e) = job.call(GluonDateTime(DateTime::from_utc(now, Utc.fix()))) {
+                    eprintln!("Error running job handler:");
+                    print_gluon_err(e.into());
                 }
             }
-        })
-        .collect();
+            // From when the job is done, so that the times missed while suspended or running don't fire back
+            // to back
+            let done = jobs.now().max(DateTime::from_utc(now, Utc));
+            cron.next_after(&done).map(|next| Job::Cron {
+                start: next.naive_utc(),
+                cron,
+                job,
+            })
+        }
+        Job::Stored {
+            key, mut def, mut job, ..
+        } => {
+            if !skip {
+                call_stored(&mut job, &def.args);
+            }
+            let mut store = wait_store();
+            if def.advance() {
+                if let Err(e) = store.set_job(key, &def) {
+                    eprintln!("Error saving job {}: {}", key, e);
+                }
+                Some(Job::Stored {
+                    start: def.next.0.naive_utc(),
+                    key,
+                    def,
+                    job,
+                })
+            } else {
+                if let Err(e) = store.del_job(key) {
+                    eprintln!("Error removing job {}: {}", key, e);
+                }
+                None
+            }
+        }
+        Job::Native { job, .. } => {
+            job();
+            None
+        }
+    }
+}
+
+fn call_stored(job: &mut StoredFunc, args: &AttrValue) {
+    if let Err(e) = job.call(args.clone()) {
+        eprintln!("Error running job handler:");
+        print_gluon_err(e.into());
+    }
+}
+
+fn resolve(vm: &Thread, func: &str) -> StorageResult<StoredFunc> {
+    vm.get_global(func).map_err(|_| Error::JobFunc(func.into()))
+}
+
+/// Create a job that survives restarts
+fn persist(func: WithVM<&str>, args: AttrValue, schedule: OptRepeated, missed: Missed) -> StorageResult<u32> {
+    let WithVM { vm, value: func } = func;
+    let job = resolve(vm, func)?;
+    schedule.validate()?;
+    let mut schedule = schedule;
+    let next = match schedule {
+        OptRepeated::Single(time) => time,
+        OptRepeated::Repeat(ref mut repeat) => repeat.next().ok_or(Error::EmptySchedule)?,
+    };
+    let def = JobDef {
+        func: func.into(),
+        args,
+        schedule,
+        next,
+        missed,
+    };
+    let key = lock_store()?.create_job(&def)?;
+    Ok(schedule(Job::Stored {
+        start: def.next.0.naive_utc(),
+        key,
+        def,
+        job,
+    }))
+}
+
+/// Move `def` past `now`. Returns how many times to run it for the occurrences missed according to its policy,
+/// and whether it has no occurrences left
+fn catch_up(def: &mut JobDef, now: DateTime<Utc>) -> (u32, bool) {
+    let mut missed = 0;
+    let mut done = false;
+    while def.next.0 <= now {
+        missed += 1;
+        if !def.advance() {
+            done = true;
+            break;
+        }
+    }
+    let runs = match def.missed {
+        Missed::Once => missed.min(1),
+        Missed::All => missed.min(MAX_CATCH_UP),
+        Missed::Skip => 0,
+    };
+    (runs, done)
+}
+
+/// Load the stored jobs, and catch up on the occurrences missed while sched wasn't running according to
+/// their policies. Must be run after the functions the jobs refer to are loaded
+pub fn rearm(vm: &Thread) {
+    let defs = match wait_store().get_jobs() {
+        Ok(defs) => defs,
+        Err(e) => {
+            eprintln!("Error loading jobs: {}", e);
+            return;
+        }
+    };
+    let now = JOBS.now();
+    for (key, mut def) in defs {
+        let mut job = match resolve(vm, &def.func) {
+            Ok(job) => job,
+            Err(e) => {
+                eprintln!("Can't re-arm job {}: {}", key, e);
+                continue;
+            }
+        };
+        let (runs, done) = catch_up(&mut def, now);
+        if JOBS.previewing() && runs > 0 {
+            eprintln!("Preview: not catching up on {} runs of the {} job", runs, def.func);
+        } else {
+            for _ in 0..runs {
+                call_stored(&mut job, &def.args);
+            }
+        }
+        let mut store = wait_store();
+        let res = if done {
+            store.del_job(key)
+        } else {
+            schedule(Job::Stored {
+                start: def.next.0.naive_utc(),
+                key,
+                def: def.clone(),
+                job,
+            });
+            store.set_job(key, &def)
+        };
+        if let Err(e) = res {
+            eprintln!("Error saving job {}: {}", key, e);
+        }
+    }
+}
+
+/// Schedule a one-shot job on `jobs` from the Rust side
+pub fn at<F: FnOnce() + Send + 'static>(jobs: &Jobs, time: DateTime<Utc>, desc: &str, job: F) -> u32 {
+    push(
+        jobs,
+        Job::Native {
+            start: time.naive_utc(),
+            desc: desc.into(),
+            job: Box::new(job),
+        },
+    )
+}
+
+/// Start the job runner on its own thread, next to the REPL
+pub fn spawn_runner() {
+    thread::spawn(move || {
+        let mut runtime = Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .expect("job runtime");
+        runtime.block_on(run_loop(&JOBS));
+    });
+}
+
+/// Run `job` at the times given by a cron expression
+fn cron(expr: &str, job: TimedFunc) -> Result<u32, String> {
+    let cron: Cron = expr.parse().map_err(|e: CronError| e.to_string())?;
+    let start = cron
+        .next_after(&JOBS.now())
+        .ok_or_else(|| format!("'{}' never matches", expr))?;
+    Ok(schedule(Job::Cron {
+        start: start.naive_utc(),
+        cron,
+        job,
+    }))
 }
 
-fn counted_at(time: GluonDateTime, interval: GluonDuration, count: u32, job: CountedFunc) {
+/// Drop the jobs of `jobs` with the given ids. This doesn't touch stored jobs in the storage
+pub fn unschedule(jobs: &Jobs, ids: &[u32]) {
+    jobs.queue.lock().unwrap().jobs.retain(|(id, _)| !ids.contains(id));
+}
+
+fn list_in(jobs: &Jobs) -> Vec<JobInfo> {
+    let queue = jobs.queue.lock().unwrap();
+    let mut infos = queue.jobs.iter().map(|(id, j)| j.info(*id)).collect::<Vec<_>>();
+    infos.sort_by_key(|i| i.next);
+    infos
+}
+
+fn list(_: ()) -> Vec<JobInfo> {
+    list_in(&JOBS)
+}
+
+/// Cancel a job of `jobs`. Returns whether the job existed. Jobs cancelled while running aren't put back once
+/// done
+fn cancel_in(jobs: &Jobs, id: u32) -> StorageResult<bool> {
+    // Lock the store before the jobs like the runner does, so that a stored job can't be saved back in between
+    let mut store = lock_store()?;
+    let removed = {
+        let mut queue = jobs.queue.lock().unwrap();
+        if queue.running.contains(&id) {
+            queue.cancelled.push(id);
+            return Ok(true);
+        }
+        let index = queue.jobs.iter().position(|(i, _)| *i == id);
+        index.map(|i| queue.jobs.remove(i).1)
+    };
+    match removed {
+        Some(Job::Stored { key, .. }) => store.del_job(key).map(|_| true),
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+fn cancel(id: u32) -> StorageResult<bool> {
+    cancel_in(&JOBS, id)
+}
+
+fn counted_at(time: GluonDateTime, interval: GluonDuration, count: u32, job: CountedFunc) -> Option<u32> {
     if count == 0 {
-        return;
+        return None;
     }
-    JOBS.lock().unwrap().push(Job::Counted {
+    Some(schedule(Job::Counted {
         start: time.0.naive_utc(),
         interval: interval.0,
         count,
         job,
-    });
+    }))
 }
 
-fn until_at(time: GluonDateTime, interval: GluonDuration, stop: GluonDateTime, job: TimedFunc) {
+fn until_at(time: GluonDateTime, interval: GluonDuration, stop: GluonDateTime, job: TimedFunc) -> Option<u32> {
     if stop.0 < time.0 {
-        return;
+        return None;
     }
-    JOBS.lock().unwrap().push(Job::Until {
+    Some(schedule(Job::Until {
         start: time.0.naive_utc(),
         interval: interval.0,
         stop: stop.0.naive_utc(),
         job,
-    });
+    }))
 }
 
-fn custom_at(time: GluonDateTime, next: NextTimeFunc, job: TimedFunc) {
-    JOBS.lock().unwrap().push(Job::Custom {
+fn custom_at(time: GluonDateTime, next: NextTimeFunc, job: TimedFunc) -> u32 {
+    schedule(Job::Custom {
         start: time.0.naive_utc(),
         next,
         job,
-    });
+    })
 }
 
-fn counted_now(count: u32, interval: GluonDuration, job: CountedFunc) {
+fn counted_now(count: u32, interval: GluonDuration, job: CountedFunc) -> Option<u32> {
     if count == 0 {
-        return;
+        return None;
     }
-    JOBS.lock().unwrap().push(Job::Counted {
-        start: Utc::now().naive_utc(),
+    Some(schedule(Job::Counted {
+        start: JOBS.now().naive_utc(),
         interval: interval.0,
         count,
         job,
-    });
+    }))
 }
 
-fn until_now(stop: GluonDateTime, interval: GluonDuration, job: TimedFunc) {
-    if stop.0 < Utc::now() {
-        return;
+fn until_now(stop: GluonDateTime, interval: GluonDuration, job: TimedFunc) -> Option<u32> {
+    let now = JOBS.now();
+    if stop.0 < now {
+        return None;
     }
-    JOBS.lock().unwrap().push(Job::Until {
-        start: Utc::now().naive_utc(),
+    Some(schedule(Job::Until {
+        start: now.naive_utc(),
         interval: interval.0,
         stop: stop.0.naive_utc(),
         job,
-    });
+    }))
 }
 
-fn custom_now(next: NextTimeFunc, job: TimedFunc) {
-    JOBS.lock().unwrap().push(Job::Custom {
-        start: Utc::now().naive_utc(),
+fn custom_now(next: NextTimeFunc, job: TimedFunc) -> u32 {
+    schedule(Job::Custom {
+        start: JOBS.now().naive_utc(),
         next,
         job,
-    });
+    })
 }
 
 pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
     ExternModule::new(
         thread,
         record! {
+            type JobInfo => JobInfo,
+            type Missed => Missed,
             counted_at => primitive!(4, counted_at),
             until_at => primitive!(4, until_at),
             custom_at => primitive!(3, custom_at),
             counted_now => primitive!(3, counted_now),
             until_now => primitive!(3, until_now),
             custom_now => primitive!(2, custom_now),
+            cron => primitive!(2, cron),
+            persist => primitive!(4, persist),
+            list => primitive!(1, list),
+            cancel => primitive!(1, cancel),
         },
     )
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::clock::{FakeClock, SystemClock};
+    use crate::storage::{Every, Repeated, Stop, Storage};
+
+    #[test]
+    fn test_run_by_clock() {
+        static RUNS: AtomicU32 = AtomicU32::new(0);
+        let clock = Arc::new(FakeClock::new(Utc.ymd(2021, 3, 10).and_hms(12, 0, 0)));
+        let jobs = Jobs::new(clock.clone());
+        let id = at(&jobs, clock.now() + Duration::minutes(10), "test", || {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+        });
+        clock.advance(Duration::minutes(9));
+        run(&jobs);
+        assert_eq!(RUNS.load(Ordering::SeqCst), 0);
+        clock.advance(Duration::minutes(1));
+        run(&jobs);
+        assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+        assert!(list_in(&jobs).iter().all(|j| j.id != id));
+    }
+
+    #[test]
+    fn test_cancel_running() {
+        let jobs = Jobs::new(Arc::new(SystemClock));
+        let id = at(&jobs, Utc.ymd(2021, 3, 10).and_hms(12, 0, 0), "test", || ());
+        // Taken out like `run` does
+        let job = {
+            let mut queue = jobs.queue.lock().unwrap();
+            let index = queue.jobs.iter().position(|(i, _)| *i == id).unwrap();
+            queue.running.push(id);
+            queue.jobs.remove(index)
+        };
+        assert!(cancel_in(&jobs, id).unwrap());
+        push_all(&jobs, &[id], vec![job]);
+        assert!(list_in(&jobs).is_empty());
+        assert!(!cancel_in(&jobs, id).unwrap());
+    }
+
+    /// An hourly job from 9:00, as loaded back from a store
+    fn stored(missed: Missed) -> JobDef {
+        let hourly = Repeated::new(
+            vec![Utc.ymd(2021, 3, 10).and_hms(9, 0, 0).into()],
+            Every::Time(Duration::hours(1).into()),
+            Stop::Nonstop,
+        );
+        let mut schedule = OptRepeated::Repeat(hourly);
+        let next = match schedule {
+            OptRepeated::Repeat(ref mut repeat) => repeat.next().unwrap(),
+            OptRepeated::Single(_) => unreachable!(),
+        };
+        let def = JobDef {
+            func: "test".into(),
+            args: AttrValue::Null,
+            schedule,
+            next,
+            missed,
+        };
+        let mut store = Storage::in_memory(Arc::new(SystemClock));
+        let key = store.create_job(&def).unwrap();
+        let (_, def) = store.get_jobs().unwrap().into_iter().find(|(k, _)| *k == key).unwrap();
+        def
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let now = Utc.ymd(2021, 3, 10).and_hms(11, 30, 0);
+        for &(missed, runs) in &[(Missed::Once, 1), (Missed::All, 3), (Missed::Skip, 0)] {
+            let mut def = stored(missed);
+            assert_eq!(catch_up(&mut def, now), (runs, false), "{:?}", missed);
+            assert_eq!(def.next, Utc.ymd(2021, 3, 10).and_hms(12, 0, 0).into());
+            // Nothing is missed twice
+            assert_eq!(catch_up(&mut def, now), (0, false));
+        }
+
+        let mut def = stored(Missed::All);
+        assert_eq!(catch_up(&mut def, now + Duration::days(365)), (MAX_CATCH_UP, false));
+
+        let mut def = stored(Missed::All);
+        def.schedule = OptRepeated::Single(def.next);
+        assert_eq!(catch_up(&mut def, now), (1, true));
+    }
+}
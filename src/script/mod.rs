@@ -1,5 +1,14 @@
+pub mod agenda;
+mod cal;
 pub mod cmd;
+mod focus;
+pub mod job;
+mod notify;
+pub mod overdue;
+mod prompt;
+pub mod reminder;
 pub mod sched;
+mod stats;
 pub mod task;
 pub mod time;
 mod tui;
@@ -13,22 +22,51 @@ use gluon::{
     Result as GluonResult, RootedThread, ThreadExt, VmBuilder,
 };
 
-pub fn get_vm(config_dir: PathBuf) -> RootedThread {
+use crate::storage::Storage;
+
+pub fn get_vm(config_dir: PathBuf, storage: Storage) -> RootedThread {
+    sched::set_store(storage);
     let vm = VmBuilder::new().import_paths(Some(vec![config_dir])).build();
     vm.run_io(true);
     add_extern_module(&vm, "sched.time.prim", time::load);
     add_extern_module(&vm, "sched.cmd.prim", cmd::load);
     add_extern_module(&vm, "sched.tui", tui::load);
     add_extern_module(&vm, "sched.util.prim", util::load);
+    add_extern_module(&vm, "sched.notify", notify::load);
     add_extern_module_with_deps(
         &vm,
         "sched.base.prim",
         sched::load,
         vec!["std.map".into(), "sched.time.prim".into(), "std.json".into()],
     );
+    add_extern_module_with_deps(&vm, "sched.prompt.prim", prompt::load, vec!["sched.time.prim".into()]);
+    add_extern_module_with_deps(&vm, "sched.focus", focus::load, vec!["sched.time.prim".into()]);
+    add_extern_module_with_deps(
+        &vm,
+        "sched.job.prim",
+        job::load,
+        vec!["sched.time.prim".into(), "sched.base.prim".into(), "std.json".into()],
+    );
+    add_extern_module_with_deps(
+        &vm,
+        "sched.agenda",
+        agenda::load,
+        vec!["sched.time.prim".into(), "sched.base.prim".into()],
+    );
+    add_extern_module_with_deps(&vm, "sched.cal", cal::load, vec!["sched.time.prim".into()]);
+    add_extern_module_with_deps(&vm, "sched.stats", stats::load, vec!["sched.time.prim".into()]);
+    add_extern_module_with_deps(&vm, "sched.reminder", reminder::load, vec!["sched.time.prim".into()]);
+    job::spawn_runner();
     vm
 }
 
+/// Keep a preview from reaching outside of sched: notifications go to stderr, and jobs defined by scripts are
+/// only printed
+pub fn preview() {
+    notify::preview();
+    job::JOBS.set_preview();
+}
+
 pub fn run_user(vm: &RootedThread, init_file: &Path) -> GluonResult<()> {
     let script = read_to_string(init_file)?;
     vm.load_script(init_file.to_str().unwrap(), &script)?;
@@ -0,0 +1,212 @@
+#[cfg(feature = "dbus")]
+mod bus;
+
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use gluon::{
+    vm::{
+        api::{OwnedFunction, IO},
+        ExternModule,
+    },
+    Thread,
+};
+use lazy_static::lazy_static;
+
+use crate::util::print_gluon_err;
+
+const APP_NAME: &str = "Sched";
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub icon: String,
+    pub urgency: Urgency,
+    /// Milliseconds until the notification expires. -1 leaves it to the server, and 0 never expires
+    pub timeout: i32,
+    /// Pairs of action keys and labels
+    pub actions: Vec<(String, String)>,
+}
+
+/// Called with the key of the action the user picked
+pub type ActionCallback = Box<dyn FnMut(&str) + Send>;
+
+/// Ids of the notifications shown with `notify-send`, which doesn't tell them
+static COMMAND_IDS: AtomicU32 = AtomicU32::new(1);
+
+/// Where notifications are shown
+enum Backend {
+    #[cfg(feature = "dbus")]
+    Bus(bus::Notifier),
+    /// `notify-send`, or stderr without it
+    Command,
+    /// Only stderr, for previews
+    Stderr,
+}
+
+impl Backend {
+    fn connect() -> Backend {
+        #[cfg(feature = "dbus")]
+        match bus::Notifier::connect() {
+            Ok(notifier) => return Backend::Bus(notifier),
+            Err(e) => eprintln!("Can't reach the notification server, using notify-send: {}", e),
+        }
+        Backend::Command
+    }
+
+    fn notify(&self, n: Notification, on_action: Option<ActionCallback>) -> Result<u32, String> {
+        match self {
+            #[cfg(feature = "dbus")]
+            Backend::Bus(notifier) => notifier.notify(n, on_action),
+            Backend::Command => {
+                notify_send(n, on_action);
+                Ok(COMMAND_IDS.fetch_add(1, Ordering::SeqCst))
+            }
+            Backend::Stderr => {
+                eprintln!("{}: {}", n.summary, n.body);
+                Ok(COMMAND_IDS.fetch_add(1, Ordering::SeqCst))
+            }
+        }
+    }
+
+    fn close(&self, id: u32) -> Result<(), String> {
+        match self {
+            #[cfg(feature = "dbus")]
+            Backend::Bus(notifier) => notifier.close(id),
+            Backend::Command => Err(format!("Can't close notification {} shown with notify-send", id)),
+            Backend::Stderr => Ok(()),
+        }
+    }
+}
+
+/// Show `n` with `notify-send`, or print it if that fails. Versions of `notify-send` with `--action` wait for
+/// an action to be picked and print its key, so they are run on their own thread
+fn notify_send(n: Notification, on_action: Option<ActionCallback>) {
+    let urgency = match n.urgency {
+        Urgency::Low => "low",
+        Urgency::Normal => "normal",
+        Urgency::Critical => "critical",
+    };
+    let mut cmd = Command::new("notify-send");
+    cmd.args(["--app-name", APP_NAME, "--urgency", urgency]);
+    if n.timeout >= 0 {
+        cmd.arg(format!("--expire-time={}", n.timeout));
+    }
+    if !n.icon.is_empty() {
+        cmd.arg("--icon").arg(&n.icon);
+    }
+    for (key, label) in &n.actions {
+        cmd.arg(format!("--action={}={}", key, label));
+    }
+    let child = cmd.arg(&n.summary).arg(&n.body).stdout(Stdio::piped()).spawn();
+    thread::spawn(move || match child.and_then(|child| child.wait_with_output()) {
+        Ok(output) if output.status.success() => {
+            let key = String::from_utf8_lossy(&output.stdout);
+            match (on_action, key.trim()) {
+                (Some(mut on_action), key) if !key.is_empty() => on_action(key),
+                _ => (),
+            }
+        }
+        _ => eprintln!("{}: {}", n.summary, n.body),
+    });
+}
+
+lazy_static! {
+    /// Connected on first use, so that sched still works without a session bus
+    static ref BACKEND: Mutex<Option<Backend>> = Mutex::new(None);
+}
+
+/// Print notifications to stderr instead of showing them
+pub fn preview() {
+    *BACKEND.lock().unwrap() = Some(Backend::Stderr);
+}
+
+fn with_backend<T, F: FnOnce(&Backend) -> Result<T, String>>(f: F) -> Result<T, String> {
+    let mut backend = BACKEND.lock().unwrap();
+    f(backend.get_or_insert_with(Backend::connect))
+}
+
+type ActionHandler = OwnedFunction<fn(()) -> IO<()>>;
+
+#[derive(VmType, Getable)]
+struct Action {
+    key: String,
+    label: String,
+    handler: ActionHandler,
+}
+
+#[derive(VmType, Getable)]
+struct GluonNotification {
+    summary: String,
+    body: String,
+    icon: String,
+    urgency: Urgency,
+    timeout: i32,
+    actions: Vec<Action>,
+}
+
+fn send(n: GluonNotification) -> IO<u32> {
+    let mut handlers = HashMap::new();
+    let notification = Notification {
+        summary: n.summary,
+        body: n.body,
+        icon: n.icon,
+        urgency: n.urgency,
+        timeout: n.timeout,
+        actions: n
+            .actions
+            .into_iter()
+            .map(|a| {
+                handlers.insert(a.key.clone(), a.handler);
+                (a.key, a.label)
+            })
+            .collect(),
+    };
+    let on_action: Option<ActionCallback> = if handlers.is_empty() {
+        None
+    } else {
+        Some(Box::new(move |key: &str| {
+            if let Some(handler) = handlers.get_mut(key) {
+                if let Err(e) = handler.call(()) {
+                    eprintln!("Error running notification action:");
+                    print_gluon_err(e.into());
+                }
+            }
+        }))
+    };
+    match with_backend(|backend| backend.notify(notification, on_action)) {
+        Ok(id) => IO::Value(id),
+        Err(e) => IO::Exception(e),
+    }
+}
+
+fn close(id: u32) -> IO<()> {
+    match with_backend(|backend| backend.close(id)) {
+        Ok(()) => IO::Value(()),
+        Err(e) => IO::Exception(e),
+    }
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            type Urgency => Urgency,
+            type Action => Action,
+            type Notification => GluonNotification,
+            send => primitive!(1, send),
+            close => primitive!(1, close),
+        },
+    )
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::Connection,
+    message::MatchRule,
+};
+
+use super::{ActionCallback, Notification, Urgency, APP_NAME};
+
+const DEST: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+/// How long to wait for the notification server to reply
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the notifier thread checks for signals from the server
+const POLL: Duration = Duration::from_millis(100);
+
+enum Request {
+    Notify(Notification, Option<ActionCallback>, Sender<Result<u32, String>>),
+    Close(u32),
+}
+
+/// Handle to a thread talking to the notification server over the session bus
+pub struct Notifier {
+    requests: Sender<Request>,
+}
+
+impl Notifier {
+    pub fn connect() -> Result<Notifier, String> {
+        let conn = Connection::new_session().map_err(|e| e.to_string())?;
+        let (requests, receiver) = channel();
+        let (ready, ready_recv) = channel();
+        thread::spawn(move || {
+            let callbacks = Arc::new(Mutex::new(HashMap::new()));
+            match watch(&conn, &callbacks) {
+                Ok(()) => {
+                    ready.send(Ok(())).unwrap();
+                    serve(conn, receiver, callbacks);
+                }
+                Err(e) => ready.send(Err(e)).unwrap(),
+            }
+        });
+        ready_recv.recv().map_err(|e| e.to_string())??;
+        Ok(Notifier { requests })
+    }
+
+    /// Show a notification and return its id. `on_action` is called when one of its actions is invoked
+    pub fn notify(&self, n: Notification, on_action: Option<ActionCallback>) -> Result<u32, String> {
+        let (reply, reply_recv) = channel();
+        self.requests
+            .send(Request::Notify(n, on_action, reply))
+            .map_err(|e| e.to_string())?;
+        reply_recv.recv().map_err(|e| e.to_string())?
+    }
+
+    pub fn close(&self, id: u32) -> Result<(), String> {
+        self.requests.send(Request::Close(id)).map_err(|e| e.to_string())
+    }
+}
+
+type Callbacks = Arc<Mutex<HashMap<u32, ActionCallback>>>;
+
+/// Call the callback of notification `id` with the action the user picked
+fn dispatch(callbacks: &Callbacks, id: u32, action: &str) {
+    if let Some(callback) = callbacks.lock().unwrap().get_mut(&id) {
+        callback(action);
+    }
+}
+
+/// Drop the callback of notification `id`, as its actions can't be picked anymore
+fn forget(callbacks: &Callbacks, id: u32) {
+    callbacks.lock().unwrap().remove(&id);
+}
+
+fn watch(conn: &Connection, callbacks: &Callbacks) -> Result<(), String> {
+    let invoked = callbacks.clone();
+    conn.add_match(
+        MatchRule::new_signal(DEST, "ActionInvoked"),
+        move |(id, action): (u32, String), _, _| {
+            dispatch(&invoked, id, &action);
+            true
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    let closed = callbacks.clone();
+    conn.add_match(
+        MatchRule::new_signal(DEST, "NotificationClosed"),
+        move |(id, _reason): (u32, u32), _, _| {
+            forget(&closed, id);
+            true
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn serve(conn: Connection, requests: Receiver<Request>, callbacks: Callbacks) {
+    let proxy = conn.with_proxy(DEST, PATH, TIMEOUT);
+    loop {
+        match requests.recv_timeout(POLL) {
+            Ok(Request::Notify(n, on_action, reply)) => {
+                let actions = n
+                    .actions
+                    .iter()
+                    .flat_map(|(key, label)| vec![key.clone(), label.clone()])
+                    .collect::<Vec<_>>();
+                let mut hints = PropMap::new();
+                let urgency = match n.urgency {
+                    Urgency::Low => 0u8,
+                    Urgency::Normal => 1,
+                    Urgency::Critical => 2,
+                };
+                hints.insert("urgency".into(), Variant(Box::new(urgency) as Box<dyn RefArg>));
+                let res: Result<(u32,), _> = proxy.method_call(
+                    DEST,
+                    "Notify",
+                    (APP_NAME, 0u32, n.icon, n.summary, n.body, actions, hints, n.timeout),
+                );
+                let res = res.map(|(id,)| id).map_err(|e| e.to_string());
+                if let (Ok(id), Some(on_action)) = (&res, on_action) {
+                    callbacks.lock().unwrap().insert(*id, on_action);
+                }
+                let _ = reply.send(res);
+            }
+            Ok(Request::Close(id)) => {
+                let res: Result<(), _> = proxy.method_call(DEST, "CloseNotification", (id,));
+                if let Err(e) = res {
+                    eprintln!("Error closing notification {}: {}", id, e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if let Err(e) = conn.process(Duration::from_millis(0)) {
+            eprintln!("Error receiving notification signals: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dbus::{channel::Sender as _, Message};
+
+    use super::*;
+
+    /// Run the notification server side on `conn`: reply to `Notify` calls with id 42, then invoke the
+    /// first action of the notification
+    fn mock_server(conn: &Connection) {
+        conn.request_name(DEST, false, true, false).unwrap();
+        conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(|msg: Message, conn: &Connection| {
+                if msg.member().as_deref() == Some("Notify") {
+                    let _ = conn.send(msg.method_return().append1(42u32));
+                    let (_, _, _, _, _, actions): (String, u32, String, String, String, Vec<String>) =
+                        msg.read6().unwrap();
+                    let signal = Message::new_signal(PATH, DEST, "ActionInvoked")
+                        .unwrap()
+                        .append2(42u32, actions[0].clone());
+                    let _ = conn.send(signal);
+                }
+                true
+            }),
+        );
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let callbacks: Callbacks = Arc::new(Mutex::new(HashMap::new()));
+        let (invoked, invoked_recv) = channel();
+        callbacks
+            .lock()
+            .unwrap()
+            .insert(42, Box::new(move |key: &str| invoked.send(key.to_string()).unwrap()));
+        // Actions of other notifications, like those of other apps, are ignored
+        dispatch(&callbacks, 7, "open");
+        dispatch(&callbacks, 42, "done");
+        forget(&callbacks, 42);
+        dispatch(&callbacks, 42, "later");
+        assert_eq!(invoked_recv.try_iter().collect::<Vec<_>>(), vec!["done"]);
+    }
+
+    // Needs a session bus without a real notification server, e.g. run with
+    // `dbus-run-session -- cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_notify_action() {
+        let server = Connection::new_session().unwrap();
+        mock_server(&server);
+        let server = thread::spawn(move || {
+            for _ in 0..50 {
+                server.process(POLL).unwrap();
+            }
+        });
+        let notifier = Notifier::connect().unwrap();
+        let (invoked, invoked_recv) = channel();
+        let id = notifier
+            .notify(
+                Notification {
+                    summary: "Sched".into(),
+                    body: "Test".into(),
+                    icon: "".into(),
+                    urgency: Urgency::Critical,
+                    timeout: -1,
+                    actions: vec![("done".into(), "Done".into())],
+                },
+                Some(Box::new(move |key| invoked.send(key.to_string()).unwrap())),
+            )
+            .unwrap();
+        assert_eq!(id, 42);
+        assert_eq!(invoked_recv.recv_timeout(Duration::from_secs(5)).unwrap(), "done");
+        server.join().unwrap();
+    }
+}
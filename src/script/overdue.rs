@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::{TimeZone, Utc};
+use lazy_static::lazy_static;
+
+use crate::{
+    attrs,
+    script::{
+        job,
+        sched::{lock_store, wait_store, Attrs, Log},
+        task::Task,
+        time::{DateTime, Duration},
+    },
+    storage::{Result as StorageResult, Storage},
+};
+
+/// Logs that may change which daughter tasks become overdue and when
+const WATCHED: &str = r"^(task\.(create|finish|skip|postpone)|obj\.set_attr)$";
+/// Longest time between checks, in case a change was missed by the watcher
+const MAX_WAIT_HOURS: i64 = 6;
+
+lazy_static! {
+    /// Id of the job of the next check
+    static ref NEXT_CHECK: Mutex<Option<u32>> = Mutex::new(None);
+    /// Number of escalations sent for each overdue daughter task since sched started
+    static ref ESCALATED: Mutex<HashMap<u32, i64>> = Mutex::new(HashMap::new());
+}
+
+/// Whether a check has been scheduled but not run yet
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug, VmType, Pushable, Getable)]
+pub struct Overdue {
+    pub task: Task,
+    /// The daughter task
+    pub log: Log,
+    /// Time since the deadline
+    pub late: Duration,
+}
+
+/// Interval between escalations of an overdue task, set by the `escalate` attribute in seconds
+fn escalation(attrs: &Attrs) -> Option<chrono::Duration> {
+    attrs
+        .get("escalate")
+        .and_then(|v| v.as_u64())
+        .filter(|&secs| secs > 0)
+        .map(|secs| chrono::Duration::seconds(secs as i64))
+}
+
+/// Flag newly overdue daughter tasks and send due escalations, counting them in `escalated`. Returns when the
+/// next check is needed
+fn check_with(
+    store: &mut Storage,
+    now: chrono::DateTime<Utc>,
+    escalated: &mut HashMap<u32, i64>,
+) -> StorageResult<chrono::DateTime<Utc>> {
+    let mut next = now + chrono::Duration::hours(MAX_WAIT_HOURS);
+    let daughters = store.unfinished_daughters()?;
+    escalated.retain(|&id, _| daughters.iter().any(|(_, l, _)| l.id == id));
+    for (task, log, overdue_at) in daughters {
+        if overdue_at > now {
+            next = next.min(overdue_at);
+            continue;
+        }
+        if !log.attrs.contains_key("overdue") {
+            let deadline = log.attrs["deadline"].clone();
+            store.log_add_attr_raw(
+                log.id,
+                "overdue".into(),
+                serde_json::to_value(DateTime::from(now)).unwrap(),
+            )?;
+            store.create_log(
+                "task.overdue".into(),
+                attrs! {
+                    "id": log.id,
+                    "task-id": task.object.id,
+                    "name": task.object.name,
+                    "deadline": deadline,
+                },
+            )?;
+            // Postponed tasks start escalating from scratch too
+            escalated.insert(log.id, 0);
+        }
+        if let Some(every) = escalation(&task.object.attrs) {
+            let count = (now - overdue_at).num_seconds() / every.num_seconds();
+            // Escalations due while sched wasn't running are not caught up on
+            let sent = *escalated.entry(log.id).or_insert(count);
+            if count > sent {
+                store.create_log(
+                    "task.escalate".into(),
+                    attrs! {
+                        "id": log.id,
+                        "task-id": task.object.id,
+                        "name": task.object.name,
+                        "count": count,
+                    },
+                )?;
+                escalated.insert(log.id, count);
+            }
+            next = next.min(overdue_at + every * (count + 1) as i32);
+        }
+    }
+    Ok(next)
+}
+
+/// Check for overdue tasks and schedule the next check
+pub fn check() {
+    PENDING.store(false, Ordering::SeqCst);
+    let mut store = wait_store();
+    let now = store.now();
+    let next = match check_with(&mut store, now, &mut ESCALATED.lock().unwrap()) {
+        Ok(next) => next,
+        Err(e) => {
+            eprintln!("Error checking for overdue tasks: {}", e);
+            now + chrono::Duration::hours(MAX_WAIT_HOURS)
+        }
+    };
+    let mut next_check = NEXT_CHECK.lock().unwrap();
+    if let Some(id) = next_check.take() {
+        job::unschedule(&job::JOBS, &[id]);
+    }
+    *next_check = Some(job::at(&job::JOBS, next, "overdue.check", check));
+}
+
+/// Start watching for changes to tasks, and check for overdue tasks
+pub fn init() {
+    let res = wait_store().add_native(
+        WATCHED,
+        Box::new(|_| {
+            // Can't touch the store from a handler, so check from the job runner
+            if !PENDING.swap(true, Ordering::SeqCst) {
+                job::at(&job::JOBS, job::JOBS.now(), "overdue.check", check);
+            }
+        }),
+    );
+    if let Err(e) = res {
+        eprintln!("Error watching for task changes: {}", e);
+    }
+    check();
+}
+
+/// The unfinished daughter tasks past their deadlines and grace periods, most late first
+pub fn list(_: ()) -> StorageResult<Vec<Overdue>> {
+    let mut store = lock_store()?;
+    let now = store.now();
+    let mut overdue = store
+        .unfinished_daughters()?
+        .into_iter()
+        .filter(|(_, _, overdue_at)| *overdue_at <= now)
+        .map(|(task, log, _)| {
+            let deadline = log.attrs["deadline"].as_i64().unwrap();
+            Overdue {
+                task,
+                log,
+                late: Duration(now - Utc.timestamp(deadline, 0)),
+            }
+        })
+        .collect::<Vec<_>>();
+    overdue.sort_by(|a, b| b.late.0.cmp(&a.late.0));
+    Ok(overdue)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{clock::FakeClock, storage::OptRepeated};
+
+    fn time(h: u32, mi: u32) -> chrono::DateTime<Utc> {
+        Utc.ymd(2021, 3, 10).and_hms(h, mi, 0)
+    }
+
+    /// A store with a task due at 10:00 with `attrs`, and the id of its daughter task
+    fn with_task(attrs: Attrs) -> (Storage, u32) {
+        let mut store = Storage::in_memory(Arc::new(FakeClock::new(time(9, 0))));
+        let task = store
+            .create_task(
+                "water",
+                "habit",
+                OptRepeated::Single(time(10, 0).into()),
+                0,
+                Some(attrs),
+            )
+            .unwrap();
+        let daughter = store.get_task(task).unwrap().cache[0];
+        (store, daughter)
+    }
+
+    fn logs(store: &mut Storage, typ: &str) -> Vec<Log> {
+        store.find_log(|l| l.typ == typ, Some(usize::MAX))
+    }
+
+    #[test]
+    fn test_grace() {
+        let mut escalated = HashMap::new();
+        let (mut store, daughter) = with_task(attrs! { "escalate": 600 });
+        // Overdue after the default grace period of 5 minutes
+        assert_eq!(
+            check_with(&mut store, time(10, 4), &mut escalated).unwrap(),
+            time(10, 5)
+        );
+        assert!(logs(&mut store, "task.overdue").is_empty());
+        check_with(&mut store, time(10, 5), &mut escalated).unwrap();
+        assert_eq!(logs(&mut store, "task.overdue").len(), 1);
+        assert!(store.get_log(daughter).unwrap().attrs.contains_key("overdue"));
+
+        let (mut store, _) = with_task(attrs! { "grace": 3600 });
+        assert_eq!(
+            check_with(&mut store, time(10, 30), &mut escalated).unwrap(),
+            time(11, 0)
+        );
+        assert!(logs(&mut store, "task.overdue").is_empty());
+    }
+
+    #[test]
+    fn test_escalate() {
+        let mut escalated = HashMap::new();
+        let (mut store, daughter) = with_task(attrs! { "escalate": 600 });
+        assert_eq!(
+            check_with(&mut store, time(10, 5), &mut escalated).unwrap(),
+            time(10, 15)
+        );
+        assert_eq!(escalated[&daughter], 0);
+        assert!(logs(&mut store, "task.escalate").is_empty());
+
+        // Escalations missed between checks are sent once, with the count they are at
+        assert_eq!(
+            check_with(&mut store, time(10, 26), &mut escalated).unwrap(),
+            time(10, 35)
+        );
+        let escalations = logs(&mut store, "task.escalate");
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].attrs["count"], 2);
+        check_with(&mut store, time(10, 30), &mut escalated).unwrap();
+        assert_eq!(logs(&mut store, "task.escalate").len(), 1);
+        // Flagged only once
+        assert_eq!(logs(&mut store, "task.overdue").len(), 1);
+
+        // Finished tasks are forgotten
+        store.task_finish(daughter, time(10, 40).into()).unwrap();
+        check_with(&mut store, time(10, 45), &mut escalated).unwrap();
+        assert!(escalated.is_empty());
+    }
+
+    #[test]
+    fn test_postpone() {
+        let mut escalated = HashMap::new();
+        let (mut store, daughter) = with_task(attrs! { "escalate": 600 });
+        check_with(&mut store, time(10, 5), &mut escalated).unwrap();
+        check_with(&mut store, time(10, 26), &mut escalated).unwrap();
+        assert_eq!(escalated[&daughter], 2);
+
+        store
+            .task_postpone(daughter, Duration(chrono::Duration::hours(1)))
+            .unwrap();
+        assert!(!store.get_log(daughter).unwrap().attrs.contains_key("overdue"));
+        check_with(&mut store, time(10, 30), &mut escalated).unwrap();
+        assert_eq!(logs(&mut store, "task.overdue").len(), 1);
+
+        // Overdue again, escalating from scratch
+        check_with(&mut store, time(11, 5), &mut escalated).unwrap();
+        assert_eq!(logs(&mut store, "task.overdue").len(), 2);
+        assert_eq!(escalated[&daughter], 0);
+        check_with(&mut store, time(11, 15), &mut escalated).unwrap();
+        let mut counts = logs(&mut store, "task.escalate")
+            .iter()
+            .map(|l| l.attrs["count"].as_i64().unwrap())
+            .collect::<Vec<_>>();
+        counts.sort();
+        assert_eq!(counts, vec![1, 2]);
+    }
+}
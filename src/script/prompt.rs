@@ -0,0 +1,203 @@
+use std::path::PathBuf;
+
+use dirs::data_dir;
+use gluon::{
+    vm::{api::IO, ExternModule},
+    Thread,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Editor, Helper,
+};
+
+use crate::{
+    clock, human,
+    script::time::{DateTime, Duration},
+};
+
+/// How a value is asked for
+#[derive(Clone, Debug, VmType, Getable)]
+pub struct Field {
+    pub label: String,
+    /// Used when the input is empty
+    pub default: Option<String>,
+    /// Candidates for tab completion. For choices, the values allowed
+    pub completions: Vec<String>,
+    /// Name of the history kept for the field, shared by the fields with the same name. Empty for no history
+    pub history: String,
+}
+
+struct FieldHelper {
+    completions: Vec<String>,
+}
+
+impl Helper for FieldHelper {}
+impl Validator for FieldHelper {}
+impl Hinter for FieldHelper {}
+impl Highlighter for FieldHelper {}
+
+impl Completer for FieldHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        let candidates = self
+            .completions
+            .iter()
+            .filter(|c| c.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+fn history_file(name: &str) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+    data_dir().map(|d| d.join(format!("sched/history-{}", name)))
+}
+
+/// Read a line for the field. Returns the default for empty lines, and fails when the input is cancelled
+fn read(field: &Field, hint: &str) -> Result<Option<String>, String> {
+    let mut editor = Editor::<FieldHelper>::new();
+    editor.set_helper(Some(FieldHelper {
+        completions: field.completions.clone(),
+    }));
+    let file = history_file(&field.history);
+    if let Some(file) = &file {
+        let _ = editor.load_history(file);
+    }
+    let prompt = match &field.default {
+        Some(default) => format!("{}{} [{}]: ", field.label, hint, default),
+        None => format!("{}{}: ", field.label, hint),
+    };
+    match editor.readline(&prompt) {
+        Ok(line) => {
+            let line = line.trim();
+            if line.is_empty() {
+                return Ok(field.default.clone());
+            }
+            editor.add_history_entry(line);
+            if let Some(file) = &file {
+                let _ = editor.save_history(file);
+            }
+            Ok(Some(line.into()))
+        }
+        Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Err("Cancelled".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Ask until the input is empty or valid
+fn ask<T, F: Fn(&str) -> Result<T, String>>(field: &Field, hint: &str, parse: F) -> IO<Option<T>> {
+    loop {
+        match read(field, hint) {
+            Ok(Some(line)) => match parse(&line) {
+                Ok(value) => return IO::Value(Some(value)),
+                Err(e) => eprintln!("{}", e),
+            },
+            Ok(None) => return IO::Value(None),
+            Err(e) => return IO::Exception(e),
+        }
+    }
+}
+
+fn parse_int(s: &str) -> Result<i64, String> {
+    s.parse().map_err(|e| format!("{}: {}", s, e))
+}
+
+/// Parse a local time with the friendly forms of `datetime.parse_human`
+fn parse_datetime(s: &str) -> Result<DateTime, String> {
+    human::parse_datetime(s, &clock::local_now())
+        .map(DateTime::from)
+        .map_err(|e| e.to_string())
+}
+
+/// Match a choice exactly, or by a prefix only one choice starts with
+fn parse_choice(s: &str, choices: &[String]) -> Result<String, String> {
+    if let Some(c) = choices.iter().find(|c| c.as_str() == s) {
+        return Ok(c.clone());
+    }
+    let mut matches = choices.iter().filter(|c| c.starts_with(s));
+    match (matches.next(), matches.next()) {
+        (Some(c), None) => Ok(c.clone()),
+        _ => Err(format!("{}: expected one of {}", s, choices.join(", "))),
+    }
+}
+
+fn parse_yes_no(s: &str) -> Result<bool, String> {
+    match s.to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(format!("{}: expected y or n", s)),
+    }
+}
+
+fn text(field: Field) -> IO<Option<String>> {
+    ask(&field, "", |s| Ok(s.to_string()))
+}
+
+fn int(field: Field) -> IO<Option<i64>> {
+    ask(&field, "", parse_int)
+}
+
+fn datetime(field: Field) -> IO<Option<DateTime>> {
+    ask(&field, "", parse_datetime)
+}
+
+fn duration(field: Field) -> IO<Option<Duration>> {
+    ask(&field, "", Duration::parse)
+}
+
+fn choice(field: Field) -> IO<Option<String>> {
+    let hint = format!(" ({})", field.completions.join("/"));
+    ask(&field, &hint, |s| parse_choice(s, &field.completions))
+}
+
+fn yes_no(field: Field) -> IO<Option<bool>> {
+    ask(&field, " (y/n)", parse_yes_no)
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            type Field => Field,
+            text => primitive!(1, text),
+            int => primitive!(1, int),
+            datetime => primitive!(1, datetime),
+            duration => primitive!(1, duration),
+            choice => primitive!(1, choice),
+            yes_no => primitive!(1, yes_no),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_datetime("2021-03-10 15:30").map(|t| t.0),
+            Ok(Local.ymd(2021, 3, 10).and_hms(15, 30, 0).into())
+        );
+        assert_eq!(
+            parse_datetime("2021-03-10").map(|t| t.0),
+            Ok(Local.ymd(2021, 3, 10).and_hms(0, 0, 0).into())
+        );
+        assert!(parse_datetime("2021-13-10").is_err());
+
+        let choices = vec!["daily".to_string(), "weekly".to_string(), "weekday".to_string()];
+        assert_eq!(parse_choice("d", &choices), Ok("daily".into()));
+        assert_eq!(parse_choice("weekly", &choices), Ok("weekly".into()));
+        assert!(parse_choice("week", &choices).is_err());
+        assert_eq!(parse_yes_no("Yes"), Ok(true));
+        assert!(parse_yes_no("maybe").is_err());
+        assert!(parse_int("1x").is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::{TimeZone, Utc};
+use gluon::{vm::ExternModule, Thread};
+use lazy_static::lazy_static;
+
+use crate::{
+    attrs,
+    script::{
+        job::{self, Jobs},
+        sched::{lock_store, wait_store, AttrValue, Object},
+        time::{DateTime, Duration},
+    },
+    storage::{is_closed, Error, Result as StorageResult, Storage},
+};
+
+/// How far ahead reminders are scheduled. They are re-armed halfway through
+const HORIZON_DAYS: i64 = 2;
+/// Meta key for the last time reminders were fired or checked
+const SEEN: &str = "reminders_seen";
+/// Logs that may change which reminders should be armed
+const WATCHED: &str = r"^(task\.(create|finish|skip|postpone)|event\.create|obj\.set_attr)$";
+
+lazy_static! {
+    /// Ids of the jobs of the currently armed reminders
+    static ref ARMED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+}
+
+/// Whether a re-arm has been scheduled but not run yet
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Parse offsets like `15m before`, `1h after`, `-15m` or `+1h`. Offsets without a direction are before
+pub fn parse_offset(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (dur, sign) = if let Some(dur) = s.strip_suffix("before").or_else(|| s.strip_prefix('-')) {
+        (dur, -1)
+    } else if let Some(dur) = s.strip_suffix("after").or_else(|| s.strip_prefix('+')) {
+        (dur, 1)
+    } else {
+        (s, -1)
+    };
+    Duration::parse(dur).map(|d| Duration(d.0 * sign))
+}
+
+fn offsets(obj: &Object) -> Vec<Duration> {
+    obj.attrs
+        .get("reminders")
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|o| o.as_str())
+                .filter_map(|o| parse_offset(o).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+struct Reminder {
+    obj: u32,
+    name: String,
+    /// The daughter task, if the reminder is for a task
+    task: Option<u32>,
+    /// Time of the deadline or event occurrence
+    time: DateTime,
+    offset: Duration,
+}
+
+impl Reminder {
+    fn fire_time(&self) -> chrono::DateTime<Utc> {
+        (self.time.0 + self.offset.0).with_timezone(&Utc)
+    }
+
+    fn fire(&self, store: &mut Storage, missed: bool) -> StorageResult<()> {
+        let mut attrs = attrs! {
+            "id": self.obj,
+            "name": self.name,
+            "time": self.time,
+            "offset": self.offset.num_seconds(),
+        };
+        if let Some(task) = self.task {
+            attrs.insert("task".into(), task.into());
+        }
+        if missed {
+            attrs.insert("missed".into(), true.into());
+        }
+        store.create_log("reminder.fire".into(), attrs)?;
+        let fired = DateTime::from(self.fire_time());
+        if store.get_meta::<DateTime>(SEEN).map_or(true, |seen| seen < fired) {
+            store.set_meta(SEEN, &fired);
+        }
+        Ok(())
+    }
+}
+
+/// Reminders firing in `(from, to]`
+fn collect(store: &mut Storage, from: DateTime, to: DateTime) -> StorageResult<Vec<Reminder>> {
+    let objs = store.find_obj(|o| o.attrs.contains_key("reminders"), Some(usize::MAX));
+    let mut reminders = Vec::new();
+    for obj in objs {
+        let offsets = offsets(&obj);
+        if offsets.is_empty() {
+            continue;
+        }
+        // The range of occurrences which can have a reminder firing in `(from, to]`
+        let latest = offsets.iter().map(|o| o.0).max().unwrap();
+        let earliest = offsets.iter().map(|o| o.0).min().unwrap();
+        let (lo, hi) = (DateTime(from.0 - latest), DateTime(to.0 - earliest));
+        let times: Vec<(Option<u32>, DateTime)> = match obj.typ.as_str() {
+            "task" => store
+                .get_task(obj.id)?
+                .cache
+                .iter()
+                .filter_map(|&id| store.get_log(id).ok())
+                .filter(|l| !is_closed(&l.attrs))
+                .filter_map(|l| {
+                    let deadline = l.attrs.get("deadline")?.as_i64()?;
+                    Some((Some(l.id), Utc.timestamp(deadline, 0).into()))
+                })
+                .collect(),
+            "event" => store
+                .get_event(obj.id)?
+                .occurrences(lo, hi)
+                .into_iter()
+                .filter(|i| lo <= i.start)
+                .map(|i| (None, i.start))
+                .collect(),
+            _ => continue,
+        };
+        for (task, time) in times {
+            for &offset in &offsets {
+                let fire = time.0 + offset.0;
+                if from.0 < fire && fire <= to.0 {
+                    reminders.push(Reminder {
+                        obj: obj.id,
+                        name: obj.name.clone(),
+                        task,
+                        time,
+                        offset,
+                    });
+                }
+            }
+        }
+    }
+    Ok(reminders)
+}
+
+/// Fire the reminders missed since the last check, and schedule the ones coming up
+pub fn rearm() {
+    PENDING.store(false, Ordering::SeqCst);
+    let mut store = wait_store();
+    let now = DateTime::from(store.now());
+    let seen = store.get_meta::<DateTime>(SEEN).unwrap_or(now);
+    if let Err(e) = rearm_with(&mut store, &job::JOBS, &mut ARMED.lock().unwrap(), seen, now) {
+        eprintln!("Error arming reminders: {}", e);
+    }
+}
+
+/// Fire the reminders missed in `(seen, now]`, and schedule the ones coming up on `jobs` in place of those in
+/// `armed`
+fn rearm_with(
+    store: &mut Storage,
+    jobs: &Jobs,
+    armed: &mut Vec<u32>,
+    seen: DateTime,
+    now: DateTime,
+) -> StorageResult<()> {
+    for reminder in collect(store, seen, now)? {
+        reminder.fire(store, true)?;
+    }
+    store.set_meta(SEEN, &now);
+    let horizon = DateTime(now.0 + chrono::Duration::days(HORIZON_DAYS));
+    job::unschedule(jobs, armed);
+    armed.clear();
+    for reminder in collect(store, now, horizon)? {
+        armed.push(job::at(jobs, reminder.fire_time(), "reminder", move || {
+            if let Err(e) = reminder.fire(&mut wait_store(), false) {
+                eprintln!("Error firing reminder: {}", e);
+            }
+        }));
+    }
+    armed.push(job::at(
+        jobs,
+        now.0 + chrono::Duration::days(HORIZON_DAYS) / 2,
+        "reminder.rearm",
+        rearm,
+    ));
+    Ok(())
+}
+
+/// Start watching for changes to tasks and events, and arm the reminders. Missed reminders are fired
+/// with a `missed` attribute
+pub fn init() {
+    let res = wait_store().add_native(
+        WATCHED,
+        Box::new(|_| {
+            // Can't touch the store from a handler, so re-arm from the job runner
+            if !PENDING.swap(true, Ordering::SeqCst) {
+                job::at(&job::JOBS, job::JOBS.now(), "reminder.rearm", rearm);
+            }
+        }),
+    );
+    if let Err(e) = res {
+        eprintln!("Error watching for reminder changes: {}", e);
+    }
+    rearm();
+}
+
+/// Set the reminder offsets of a task or event
+fn set(id: u32, offsets: Vec<String>) -> StorageResult<()> {
+    for offset in &offsets {
+        parse_offset(offset).map_err(|_| Error::InvalidOffset(offset.clone()))?;
+    }
+    let offsets = offsets.into_iter().map(AttrValue::String).collect();
+    lock_store()?.obj_set_attr(id, "reminders".into(), AttrValue::Array(offsets))
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            set => primitive!(2, set),
+            parse_offset => primitive!(1, parse_offset),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        storage::{Conflicts, Every, OptRepeated, Repeated, Stop},
+    };
+
+    fn time(d: u32, h: u32, mi: u32) -> DateTime {
+        Utc.ymd(2021, 3, d).and_hms(h, mi, 0).into()
+    }
+
+    /// A task due at 10:00 reminded 15 minutes before, and a daily event at 12:00 reminded an hour after
+    fn store() -> Storage {
+        let clock = Arc::new(FakeClock::new(Utc.ymd(2021, 3, 10).and_hms(8, 0, 0)));
+        let mut store = Storage::in_memory(clock);
+        store
+            .create_task(
+                "water",
+                "habit",
+                OptRepeated::Single(time(10, 10, 0)),
+                0,
+                Some(attrs! { "reminders": ["15m"] }),
+            )
+            .unwrap();
+        let daily = Repeated::new(
+            vec![time(10, 12, 0)],
+            Every::Time(chrono::Duration::days(1).into()),
+            Stop::Nonstop,
+        );
+        store
+            .create_event(
+                "lunch",
+                "meal",
+                OptRepeated::Repeat(daily),
+                chrono::Duration::hours(1).into(),
+                Some(attrs! { "reminders": ["+1h"] }),
+                Conflicts::Allow,
+            )
+            .unwrap();
+        store
+    }
+
+    fn fire_times(reminders: &[Reminder]) -> Vec<DateTime> {
+        let mut times = reminders.iter().map(|r| r.fire_time().into()).collect::<Vec<_>>();
+        times.sort();
+        times
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        let minutes = |s| parse_offset(s).map(|d| d.num_seconds() / 60);
+        assert_eq!(minutes("15m"), Ok(-15));
+        assert_eq!(minutes("15m before"), Ok(-15));
+        assert_eq!(minutes("-15m"), Ok(-15));
+        assert_eq!(minutes("+1h"), Ok(60));
+        assert_eq!(minutes("1h after"), Ok(60));
+        assert!(parse_offset("soon").is_err());
+        assert!(parse_offset("+").is_err());
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut store = store();
+        let reminders = collect(&mut store, time(10, 8, 0), time(11, 8, 0)).unwrap();
+        assert_eq!(fire_times(&reminders), vec![time(10, 9, 45), time(10, 13, 0)]);
+        let task = reminders.iter().find(|r| r.name == "water").unwrap();
+        assert!(task.task.is_some());
+        assert_eq!(task.time, time(10, 10, 0));
+
+        // The range is exclusive at the start
+        let reminders = collect(&mut store, time(10, 9, 45), time(11, 13, 0)).unwrap();
+        assert_eq!(fire_times(&reminders), vec![time(10, 13, 0), time(11, 13, 0)]);
+
+        // Closed tasks aren't reminded of
+        store.task_finish(task.task.unwrap(), time(10, 9, 0)).unwrap();
+        let reminders = collect(&mut store, time(10, 8, 0), time(11, 8, 0)).unwrap();
+        assert_eq!(fire_times(&reminders), vec![time(10, 13, 0)]);
+    }
+
+    #[test]
+    fn test_rearm_missed() {
+        let mut store = store();
+        let (jobs, mut armed) = (Jobs::new(store.clock()), Vec::new());
+        rearm_with(&mut store, &jobs, &mut armed, time(10, 9, 0), time(10, 14, 0)).unwrap();
+
+        let fired = store.find_log(|l| l.typ == "reminder.fire", Some(usize::MAX));
+        let mut names = fired
+            .iter()
+            .map(|l| l.attrs["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["lunch", "water"]);
+        assert!(fired.iter().all(|l| l.attrs["missed"] == true));
+        assert_eq!(store.get_meta::<DateTime>(SEEN), Some(time(10, 14, 0)));
+
+        // Nothing is missed twice
+        rearm_with(&mut store, &jobs, &mut armed, time(10, 14, 0), time(10, 15, 0)).unwrap();
+        assert_eq!(store.find_log(|l| l.typ == "reminder.fire", Some(usize::MAX)).len(), 2);
+    }
+}
@@ -12,15 +12,21 @@ use lazy_static::lazy_static;
 pub use serde_json::Value as AttrValue;
 
 use crate::{
+    clock,
     script::{
-        task::{Event, Task},
+        overdue::{self, Overdue},
+        task::{Event, EventTime, Task},
         time::DateTime,
     },
-    storage::{Error, Every, OptRepeated, Repeated, Result as StorageResult, Stop, Storage},
+    storage::{
+        db_path, AllDay, Busy, Conflicts, DateEvery, Error, Every, FreeBusy, Interval, OptRepeated, Repeated,
+        Result as StorageResult, Stop, Storage,
+    },
 };
 
 lazy_static! {
-    pub static ref STORE: Mutex<Storage> = Mutex::new(Storage::new());
+    /// The store of the scripts and jobs. Empty and in memory until `get_vm` installs the one it's given
+    pub static ref STORE: Mutex<Storage> = Mutex::new(Storage::in_memory(clock::get()));
 }
 
 pub type Attrs = BTreeMap<String, AttrValue>;
@@ -46,6 +52,17 @@ pub fn lock_store() -> StorageResult<MutexGuard<'static, Storage>> {
     }
 }
 
+/// Replace the store of the scripts and jobs
+pub fn set_store(storage: Storage) {
+    *wait_store() = storage;
+}
+
+/// Lock the store for jobs and other code running outside of the REPL, which should wait for the store
+/// instead of bailing out like `lock_store`
+pub fn wait_store() -> MutexGuard<'static, Storage> {
+    STORE.lock().expect("STORE lock poisoned")
+}
+
 fn lalign(s: &str, n: usize) -> String {
     format!("{}{}", s, " ".repeat(n - s.len()))
 }
@@ -235,13 +252,29 @@ pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
                 new => primitive!(4, Task::new),
                 get => primitive!(1, Task::get),
                 finish => primitive!(1, Task::finish),
+                skip => primitive!(1, Task::skip),
+                postpone => primitive!(2, Task::postpone),
                 find_current => primitive!(1, Task::find_current),
+                type Overdue => Overdue,
+                overdue => primitive!(1, overdue::list),
             },
 
             event => record! {
                 type Event => Event,
                 new => primitive!(4, Event::new),
+                new_checked => primitive!(5, Event::new_checked),
+                new_all_day => primitive!(3, Event::new_all_day),
+                type EventTime => EventTime,
+                type AllDay => AllDay,
+                type DateEvery => DateEvery,
                 get => primitive!(1, Event::get),
+                start => primitive!(1, Event::start),
+                duration => primitive!(1, Event::duration),
+                type Conflicts => Conflicts,
+                type Interval => Interval,
+                type Busy => Busy,
+                type FreeBusy => FreeBusy,
+                free_busy => primitive!(2, Event::free_busy),
             },
 
             handle => primitive!(2, |pat, func| {
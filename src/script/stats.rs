@@ -0,0 +1,435 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, NaiveDate, TimeZone, Timelike, Utc};
+use gluon::{vm::ExternModule, Thread};
+
+use crate::{
+    clock::LocalZone,
+    script::{
+        sched::{lock_store, Log},
+        task::Task,
+        time::{add_months, start_of_week, Date, DateTime, Duration},
+    },
+    storage::{grace, is_closed, OptRepeated, Result as StorageResult, Storage},
+};
+
+/// Number of days shown in the strips of habits
+const STRIP_DAYS: i64 = 30;
+/// Number of hours listed as the busiest ones in reports
+const BUSIEST_HOURS: usize = 3;
+
+/// Status of a day in a habit strip. When a day has several daughter tasks, the greatest one is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, VmType, Pushable, Getable)]
+pub enum DayStatus {
+    /// No daughter task on that day
+    Free,
+    Done,
+    /// Not finished yet, but still within the deadline and grace period
+    Pending,
+    Late,
+    Missed,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, VmType, Pushable, Getable)]
+pub struct Rate {
+    pub finished: u32,
+    pub total: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct Habit {
+    pub task: u32,
+    pub name: String,
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub last_7: Rate,
+    pub last_30: Rate,
+    pub last_90: Rate,
+    pub on_time: u32,
+    pub late: u32,
+    pub missed: u32,
+    /// Status of each of the last `STRIP_DAYS` local days, oldest first
+    pub days: Vec<DayStatus>,
+}
+
+/// A daughter task, as far as habits are concerned
+#[derive(Clone, Copy, Debug)]
+struct Occurrence {
+    deadline: chrono::DateTime<Utc>,
+    finished: Option<chrono::DateTime<Utc>>,
+}
+
+impl Occurrence {
+    fn status(&self, grace: chrono::Duration, now: chrono::DateTime<Utc>) -> DayStatus {
+        match self.finished {
+            Some(f) if f <= self.deadline + grace => DayStatus::Done,
+            Some(_) => DayStatus::Late,
+            None if self.deadline + grace < now => DayStatus::Missed,
+            None => DayStatus::Pending,
+        }
+    }
+}
+
+fn occurrences(store: &mut Storage, task: u32) -> Vec<Occurrence> {
+    store
+        .find_log(
+            |l| l.typ == "task.task" && l.attrs.get("task-id").and_then(|v| v.as_u64()) == Some(task as u64),
+            Some(usize::MAX),
+        )
+        .into_iter()
+        .filter_map(|l| {
+            let deadline = l.attrs.get("deadline")?.as_i64()?;
+            let finished = l.attrs.get("finished").and_then(|v| v.as_i64());
+            Some(Occurrence {
+                deadline: Utc.timestamp(deadline, 0),
+                finished: finished.map(|f| Utc.timestamp(f, 0)),
+            })
+        })
+        .collect()
+}
+
+/// Compute the habit from its daughter tasks. Days in the strip are in the timezone of `now`
+fn compute<Tz: TimeZone>(
+    task: u32,
+    name: String,
+    mut occurrences: Vec<Occurrence>,
+    grace: chrono::Duration,
+    now: &chrono::DateTime<Tz>,
+) -> Habit {
+    occurrences.sort_by_key(|o| o.deadline);
+    let now_utc = now.with_timezone(&Utc);
+    let due = occurrences
+        .iter()
+        .map(|o| (o.deadline, o.status(grace, now_utc)))
+        .filter(|(_, s)| *s != DayStatus::Pending)
+        .collect::<Vec<_>>();
+
+    let (mut current_streak, mut longest_streak) = (0, 0);
+    for (_, status) in &due {
+        if *status == DayStatus::Missed {
+            current_streak = 0;
+        } else {
+            current_streak += 1;
+            longest_streak = longest_streak.max(current_streak);
+        }
+    }
+    let rate = |days| {
+        let from = now_utc - chrono::Duration::days(days);
+        due.iter()
+            .filter(|(d, _)| *d > from)
+            .fold(Rate::default(), |mut r, (_, s)| {
+                r.total += 1;
+                if *s != DayStatus::Missed {
+                    r.finished += 1;
+                }
+                r
+            })
+    };
+    let count = |status| due.iter().filter(|(_, s)| *s == status).count() as u32;
+
+    let today = now.date().naive_local();
+    let mut days = vec![DayStatus::Free; STRIP_DAYS as usize];
+    for o in &occurrences {
+        let ago = today
+            .signed_duration_since(o.deadline.with_timezone(&now.timezone()).date().naive_local())
+            .num_days();
+        if (0..STRIP_DAYS).contains(&ago) {
+            let day = &mut days[(STRIP_DAYS - 1 - ago) as usize];
+            *day = (*day).max(o.status(grace, now_utc));
+        }
+    }
+
+    Habit {
+        task,
+        name,
+        current_streak,
+        longest_streak,
+        last_7: rate(7),
+        last_30: rate(30),
+        last_90: rate(90),
+        on_time: count(DayStatus::Done),
+        late: count(DayStatus::Late),
+        missed: count(DayStatus::Missed),
+        days,
+    }
+}
+
+fn habit_with(store: &mut Storage, task: u32) -> StorageResult<Habit> {
+    let t = store.get_task(task)?;
+    let occurrences = occurrences(store, task);
+    Ok(compute(
+        task,
+        t.object.name,
+        occurrences,
+        grace(&t.object.attrs),
+        &store.now().with_timezone(&LocalZone),
+    ))
+}
+
+/// Habit statistics of the repeated task `task`
+pub fn habit(task: u32) -> StorageResult<Habit> {
+    habit_with(&mut lock_store()?, task)
+}
+
+/// Habit statistics of all repeated tasks
+pub fn habits(_: ()) -> StorageResult<Vec<Habit>> {
+    let mut store = lock_store()?;
+    let ids = store
+        .find_obj(|o| o.typ == "task", Some(usize::MAX))
+        .into_iter()
+        .map(|o| o.id)
+        .collect::<Vec<_>>();
+    let mut habits = Vec::new();
+    for id in ids {
+        if let OptRepeated::Repeat(_) = store.get_task(id)?.deadline {
+            habits.push(habit_with(&mut store, id)?);
+        }
+    }
+    Ok(habits)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum Period {
+    Day,
+    /// Weeks start on Monday
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The first day of the period containing `date`, and the first day after it
+    fn bounds(self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+        match self {
+            Period::Day => (date, date.succ()),
+            Period::Week => {
+                let start = start_of_week(date);
+                (start, start + chrono::Duration::days(7))
+            }
+            Period::Month => {
+                let start = date.with_day(1).unwrap();
+                (start, add_months(start, 1).unwrap())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct Tracked {
+    pub task_typ: String,
+    pub time: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct HourCount {
+    /// Local hour of the day
+    pub hour: u32,
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, VmType, Pushable, Getable)]
+pub struct Report {
+    pub from: DateTime,
+    /// The end of the period, exclusive
+    pub to: DateTime,
+    pub created: u32,
+    pub finished: u32,
+    /// Daughter tasks skipped in the period, or which went past their deadlines and grace periods in it
+    /// unfinished
+    pub skipped: u32,
+    /// Event occurrences which started in the period, up to now
+    pub events: u32,
+    /// Time spent in focus sessions per task type, most first
+    pub tracked: Vec<Tracked>,
+    /// Hours of the day with the most finished tasks and focus sessions, busiest first
+    pub busiest_hours: Vec<HourCount>,
+}
+
+fn attr_u32(log: &Log, key: &str) -> Option<u32> {
+    log.attrs.get(key).and_then(|v| v.as_u64()).map(|v| v as u32)
+}
+
+/// The mother task of a daughter task, cached in `tasks`
+fn task_of_daughter(store: &mut Storage, tasks: &mut HashMap<u32, Task>, daughter: u32) -> StorageResult<Option<Task>> {
+    let task = match attr_u32(&store.get_log(daughter)?, "task-id") {
+        Some(task) => task,
+        None => return Ok(None),
+    };
+    if !tasks.contains_key(&task) {
+        tasks.insert(task, store.get_task(task)?);
+    }
+    Ok(tasks.get(&task).cloned())
+}
+
+fn report_with(store: &mut Storage, period: Period, at: DateTime) -> StorageResult<Report> {
+    let (start, end) = period.bounds(at.0.with_timezone(&LocalZone).date().naive_local());
+    let (from, to) = (Date(start).start(), Date(end).start());
+    let now = DateTime::from(store.now());
+    let logs = store.find_log(|l| from <= l.time && l.time < to, Some(usize::MAX));
+    let mut tasks = HashMap::new();
+
+    let mut tracked: BTreeMap<String, chrono::Duration> = BTreeMap::new();
+    let mut hours = [0u32; 24];
+    let (mut created, mut finished, mut skipped) = (0, 0, 0);
+    for log in &logs {
+        match log.typ.as_str() {
+            "task.create" => created += 1,
+            "task.finish" => finished += 1,
+            "task.skip" => {
+                skipped += 1;
+                continue;
+            }
+            "focus.end" => {
+                let worked = log.attrs.get("worked").and_then(|v| v.as_i64()).unwrap_or(0);
+                let task = match attr_u32(log, "task") {
+                    Some(daughter) => task_of_daughter(store, &mut tasks, daughter)?,
+                    None => None,
+                };
+                if let Some(task) = task {
+                    *tracked.entry(task.task_typ).or_insert_with(chrono::Duration::zero) +=
+                        chrono::Duration::seconds(worked);
+                }
+            }
+            _ => continue,
+        }
+        if log.typ != "task.create" {
+            hours[log.time.0.with_timezone(&LocalZone).hour() as usize] += 1;
+        }
+    }
+
+    let daughters = store.find_log(|l| l.typ == "task.task" && !is_closed(&l.attrs), Some(usize::MAX));
+    for log in daughters {
+        let deadline = match log.attrs.get("deadline").and_then(|v| v.as_i64()) {
+            Some(deadline) => Utc.timestamp(deadline, 0),
+            None => continue,
+        };
+        if let Some(task) = task_of_daughter(store, &mut tasks, log.id)? {
+            let due = DateTime::from(deadline + grace(&task.object.attrs));
+            if from <= due && due < to && due < now {
+                skipped += 1;
+            }
+        }
+    }
+
+    let event_ids = store
+        .find_obj(|o| o.typ == "event", Some(usize::MAX))
+        .into_iter()
+        .map(|o| o.id)
+        .collect::<Vec<_>>();
+    let mut events = 0;
+    for id in event_ids {
+        let event = store.get_event(id)?;
+        events += event
+            .occurrences(from, to.min(now))
+            .into_iter()
+            .filter(|i| from <= i.start)
+            .count() as u32;
+    }
+
+    let mut tracked = tracked
+        .into_iter()
+        .map(|(task_typ, time)| Tracked {
+            task_typ,
+            time: Duration(time),
+        })
+        .collect::<Vec<_>>();
+    tracked.sort_by(|a, b| b.time.0.cmp(&a.time.0));
+    let mut busiest_hours = (0..24)
+        .map(|hour| HourCount {
+            hour,
+            count: hours[hour as usize],
+        })
+        .filter(|h| h.count > 0)
+        .collect::<Vec<_>>();
+    busiest_hours.sort_by(|a, b| b.count.cmp(&a.count));
+    busiest_hours.truncate(BUSIEST_HOURS);
+
+    Ok(Report {
+        from,
+        to,
+        created,
+        finished,
+        skipped,
+        events,
+        tracked,
+        busiest_hours,
+    })
+}
+
+/// Statistics of the day, week or month containing `at`, in local time
+pub fn report(period: Period, at: DateTime) -> StorageResult<Report> {
+    report_with(&mut lock_store()?, period, at)
+}
+
+pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    ExternModule::new(
+        thread,
+        record! {
+            type DayStatus => DayStatus,
+            type Rate => Rate,
+            type Habit => Habit,
+            habit => primitive!(1, habit),
+            habits => primitive!(1, habits),
+            type Period => Period,
+            type Tracked => Tracked,
+            type HourCount => HourCount,
+            type Report => Report,
+            report => primitive!(2, report),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn occurrence(day: u32, finished: Option<(u32, u32)>) -> Occurrence {
+        Occurrence {
+            deadline: Utc.ymd(2021, 3, day).and_hms(20, 0, 0),
+            finished: finished.map(|(d, h)| Utc.ymd(2021, 3, d).and_hms(h, 0, 0)),
+        }
+    }
+
+    #[test]
+    fn test_habit() {
+        let occurrences = vec![
+            occurrence(1, Some((1, 19))),
+            occurrence(2, Some((2, 19))),
+            occurrence(3, None),
+            occurrence(4, Some((5, 9))),
+            occurrence(5, Some((5, 20))),
+            occurrence(6, Some((6, 12))),
+            occurrence(7, None),
+            occurrence(8, None),
+        ];
+        let now = Utc.ymd(2021, 3, 7).and_hms(12, 0, 0);
+        let habit = compute(1, "".into(), occurrences, chrono::Duration::minutes(5), &now);
+        assert_eq!(habit.current_streak, 3);
+        assert_eq!(habit.longest_streak, 3);
+        assert_eq!((habit.on_time, habit.late, habit.missed), (4, 1, 1));
+        assert_eq!(habit.last_7, Rate { finished: 5, total: 6 });
+        assert_eq!(habit.days.len(), STRIP_DAYS as usize);
+        use DayStatus::*;
+        assert_eq!(
+            &habit.days[STRIP_DAYS as usize - 8..],
+            &[Free, Done, Done, Missed, Late, Done, Done, Pending]
+        );
+    }
+
+    #[test]
+    fn test_period_bounds() {
+        // Wednesday
+        let date = NaiveDate::from_ymd(2020, 12, 30);
+        assert_eq!(Period::Day.bounds(date), (date, NaiveDate::from_ymd(2020, 12, 31)));
+        assert_eq!(
+            Period::Week.bounds(date),
+            (NaiveDate::from_ymd(2020, 12, 28), NaiveDate::from_ymd(2021, 1, 4))
+        );
+        assert_eq!(
+            Period::Month.bounds(date),
+            (NaiveDate::from_ymd(2020, 12, 1), NaiveDate::from_ymd(2021, 1, 1))
+        );
+        let date = NaiveDate::from_ymd(2021, 2, 28);
+        assert_eq!(Period::Week.bounds(date).0, NaiveDate::from_ymd(2021, 2, 22));
+        assert_eq!(Period::Month.bounds(date).1, NaiveDate::from_ymd(2021, 3, 1));
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
     script::{
         sched::{lock_store, Object},
-        time::Duration,
+        time::{Date, DateTime, Duration},
     },
-    storage::{OptRepeated, Result as StorageResult},
+    storage::{AllDay, Conflicts, FreeBusy, Interval, OptRepeated, Result as StorageResult},
 };
 
 #[derive(Clone, Debug, VmType, Pushable, Getable)]
@@ -27,7 +27,19 @@ impl Task {
     }
 
     pub fn finish(id: u32) -> StorageResult<()> {
-        lock_store()?.task_finish(id, chrono::Local::now().into())
+        let mut store = lock_store()?;
+        let now = store.now();
+        store.task_finish(id, now.into())
+    }
+
+    pub fn skip(id: u32) -> StorageResult<()> {
+        let mut store = lock_store()?;
+        let now = store.now();
+        store.task_skip(id, now.into())
+    }
+
+    pub fn postpone(id: u32, by: Duration) -> StorageResult<DateTime> {
+        lock_store()?.task_postpone(id, by)
     }
 
     pub fn find_current(id: u32) -> StorageResult<Option<u32>> {
@@ -35,19 +47,84 @@ impl Task {
     }
 }
 
+#[derive(Clone, Debug, VmType, Pushable, Getable)]
+pub enum EventTime {
+    /// Start time and duration
+    Timed(OptRepeated, Duration),
+    AllDay(AllDay),
+}
+
 #[derive(Clone, Debug, VmType, Pushable, Getable)]
 pub struct Event {
     pub object: Object,
-    pub start: OptRepeated,
-    pub duration: Duration,
+    pub time: EventTime,
 }
 
 impl Event {
+    /// Occurrences overlapping `[from, to)`. All-day occurrences span from local midnight to local midnight
+    pub fn occurrences(&self, from: DateTime, to: DateTime) -> Vec<Interval> {
+        let range = Interval { start: from, end: to };
+        match &self.time {
+            EventTime::Timed(start, duration) => start
+                .occurrences(DateTime(from.0 - duration.0), to)
+                .into_iter()
+                .map(|start| Interval {
+                    start,
+                    end: DateTime(start.0 + duration.0),
+                })
+                .filter(|i| i.overlaps(&range) || (i.start == i.end && from <= i.start && i.start < to))
+                .collect(),
+            EventTime::AllDay(all_day) => all_day
+                .occurrences(Date::of(&from).0, Date::of(&to).0)
+                .into_iter()
+                .map(|date| Interval {
+                    start: Date(date).start(),
+                    end: Date(date + chrono::Duration::days(all_day.days.max(1) as i64)).start(),
+                })
+                .filter(|i| i.overlaps(&range))
+                .collect(),
+        }
+    }
+
+    /// Start time of timed events, which used to be a field of all events
+    pub fn start(self) -> Option<OptRepeated> {
+        match self.time {
+            EventTime::Timed(start, _) => Some(start),
+            EventTime::AllDay(_) => None,
+        }
+    }
+
+    /// Duration of timed events, which used to be a field of all events
+    pub fn duration(self) -> Option<Duration> {
+        match self.time {
+            EventTime::Timed(_, duration) => Some(duration),
+            EventTime::AllDay(_) => None,
+        }
+    }
+
     pub fn new(name: &str, typ: &str, start: OptRepeated, duration: Duration) -> StorageResult<u32> {
-        lock_store()?.create_event(name, typ, start, duration, None)
+        Event::new_checked(name, typ, start, duration, Conflicts::Allow)
+    }
+
+    pub fn new_checked(
+        name: &str,
+        typ: &str,
+        start: OptRepeated,
+        duration: Duration,
+        conflicts: Conflicts,
+    ) -> StorageResult<u32> {
+        lock_store()?.create_event(name, typ, start, duration, None, conflicts)
+    }
+
+    pub fn new_all_day(name: &str, typ: &str, all_day: AllDay) -> StorageResult<u32> {
+        lock_store()?.create_all_day_event(name, typ, all_day, None)
     }
 
     pub fn get(id: u32) -> StorageResult<Event> {
         lock_store()?.get_event(id)
     }
+
+    pub fn free_busy(from: DateTime, to: DateTime) -> StorageResult<FreeBusy> {
+        lock_store()?.free_busy(from, to)
+    }
 }
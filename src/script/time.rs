@@ -1,11 +1,21 @@
-use chrono::{Datelike, FixedOffset, Local, Offset, TimeZone as _, Timelike, Utc};
+use std::convert::TryFrom;
+
+use chrono::{Datelike, FixedOffset, Local, NaiveDate, Offset, TimeZone as _, Timelike, Utc};
 use gluon::{
-    vm::{api::Getable, ExternModule, Result as GluonResult, Variants},
+    vm::{
+        api::{Getable, IO},
+        ExternModule, Result as GluonResult, Variants,
+    },
     Thread,
 };
 use gluon_codegen::*;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::{
+    clock::{self, LocalZone},
+    human,
+};
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Trace, VmType, Userdata)]
 #[gluon_userdata(clone)]
 #[gluon(vm_type = "time.DateTime")]
@@ -37,30 +47,104 @@ impl From<chrono::DateTime<Local>> for DateTime {
     }
 }
 
-// We only care about seconds, so we can just discard the nanos
+impl From<chrono::DateTime<LocalZone>> for DateTime {
+    fn from(t: chrono::DateTime<LocalZone>) -> DateTime {
+        DateTime(t.with_timezone(t.offset()))
+    }
+}
+
+/// Times are stored as whole seconds since the epoch by default, dropping the fraction. Use `millis` to keep
+/// milliseconds
 impl Serialize for DateTime {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let utc_time: chrono::DateTime<Utc> = self.0.into();
-        serializer.serialize_i64(utc_time.timestamp())
+        serializer.serialize_i64(self.0.timestamp())
     }
 }
 
 impl<'de> Deserialize<'de> for DateTime {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct DateTimeVisitor;
-        use std::fmt;
-        impl<'de> de::Visitor<'de> for DateTimeVisitor {
-            type Value = DateTime;
+        let ms = deserializer.deserialize_any(MillisVisitor("DateTime"))?;
+        Utc.timestamp_millis_opt(ms)
+            .single()
+            .map(DateTime::from)
+            .ok_or_else(|| de::Error::custom(format!("timestamp {}ms out of range", ms)))
+    }
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("int DateTime")
-            }
+/// Reads every encoding of times and durations as milliseconds: whole seconds as integers, seconds with a
+/// fraction as floats, and milliseconds as `{"ms": n}`
+struct MillisVisitor(&'static str);
 
-            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
-                Ok(DateTime(Utc.timestamp(value as i64, 0).into()))
+impl<'de> de::Visitor<'de> for MillisVisitor {
+    type Value = i64;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{} as seconds or {{\"ms\": milliseconds}}", self.0)
+    }
+
+    fn visit_i64<E: de::Error>(self, secs: i64) -> Result<i64, E> {
+        secs.checked_mul(1000)
+            .ok_or_else(|| E::custom(format!("{} seconds out of range", secs)))
+    }
+
+    fn visit_u64<E: de::Error>(self, secs: u64) -> Result<i64, E> {
+        let secs = i64::try_from(secs).map_err(|_| E::custom(format!("{} seconds out of range", secs)))?;
+        self.visit_i64(secs)
+    }
+
+    fn visit_f64<E: de::Error>(self, secs: f64) -> Result<i64, E> {
+        let ms = (secs * 1000.0).round();
+        if !ms.is_finite() || ms.abs() >= i64::MAX as f64 {
+            return Err(E::custom(format!("{} seconds out of range", secs)));
+        }
+        Ok(ms as i64)
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<i64, A::Error> {
+        let mut ms = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "ms" => ms = Some(map.next_value()?),
+                _ => return Err(de::Error::unknown_field(&key, &["ms"])),
             }
         }
-        deserializer.deserialize_u64(DateTimeVisitor)
+        ms.ok_or_else(|| de::Error::missing_field("ms"))
+    }
+}
+
+/// Values with a millisecond encoding
+pub trait AsMillis {
+    fn as_millis(&self) -> i64;
+}
+
+impl AsMillis for DateTime {
+    fn as_millis(&self) -> i64 {
+        self.0.timestamp_millis()
+    }
+}
+
+impl AsMillis for Duration {
+    fn as_millis(&self) -> i64 {
+        self.0.num_milliseconds()
+    }
+}
+
+/// Millisecond precision for times and durations, with `#[serde(with = "crate::script::time::millis")]`. Values
+/// are written as `{"ms": n}`, and read back by the regular impls, which also read the default encoding of
+/// whole seconds
+pub mod millis {
+    use serde::{ser::SerializeMap, Deserialize, Deserializer, Serializer};
+
+    use super::AsMillis;
+
+    pub fn serialize<T: AsMillis, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("ms", &value.as_millis())?;
+        map.end()
+    }
+
+    pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        T::deserialize(deserializer)
     }
 }
 
@@ -80,6 +164,29 @@ macro_rules! datetime_setter {
     }
 }
 
+/// Number of days in a month, or `None` for invalid months
+pub fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let next = match month {
+        12 => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+        _ => NaiveDate::from_ymd_opt(year, month + 1, 1)?,
+    };
+    Some(next.signed_duration_since(first).num_days() as u32)
+}
+
+/// `date` moved by `months` months. Days past the end of the month are clamped to its last day
+pub fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    let month0 = date.year() as i64 * 12 + date.month0() as i64 + months as i64;
+    let (year, month) = (month0.div_euclid(12) as i32, month0.rem_euclid(12) as u32 + 1);
+    let day = date.day().min(days_in_month(year, month)?);
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+/// The Monday of the ISO week of `date`
+pub fn start_of_week(date: NaiveDate) -> NaiveDate {
+    date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
 impl DateTime {
     fn new(y: i32, m: u32, d: u32, h: u32, mi: u32, s: u32) -> Option<DateTime> {
         Utc.ymd_opt(y, m, d)
@@ -109,20 +216,32 @@ impl DateTime {
     }
 
     fn with_timezone(&self, tz: &TimeZone) -> DateTime {
-        DateTime(self.0.with_timezone(&tz.0))
+        match tz {
+            TimeZone::Fixed(offset) => DateTime(self.0.with_timezone(offset)),
+            TimeZone::Local => self.to_local(),
+        }
     }
 
+    /// The time in the local timezone, with the offset in effect at that instant
     fn to_local(&self) -> DateTime {
-        DateTime(self.0.with_timezone(Local::now().offset()))
+        self.0.with_timezone(&LocalZone).into()
     }
 
     fn local_now(_: ()) -> DateTime {
-        let now = Local::now();
-        DateTime(now.with_timezone(now.offset()))
+        clock::local_now().into()
     }
 
     fn utc_now(_: ()) -> DateTime {
-        Utc::now().into()
+        clock::now().into()
+    }
+
+    /// Parse friendly input like `tomorrow 9am`, `next fri` or `in 3h`, relative to `now` in `tz`
+    fn parse_human(s: &str, now: &DateTime, tz: &TimeZone) -> Result<DateTime, String> {
+        match tz {
+            TimeZone::Fixed(offset) => human::parse_datetime(s, &now.0.with_timezone(offset)).map(DateTime),
+            TimeZone::Local => human::parse_datetime(s, &now.0.with_timezone(&LocalZone)).map(DateTime::from),
+        }
+        .map_err(|e| e.to_string())
     }
 
     fn sub(&self, b: &DateTime) -> Duration {
@@ -140,6 +259,206 @@ impl DateTime {
     fn lt(&self, b: &DateTime) -> bool {
         self.0 < b.0
     }
+
+    /// Day of the week, from 0 for Monday to 6 for Sunday
+    fn weekday(&self) -> u32 {
+        self.0.weekday().num_days_from_monday()
+    }
+
+    fn iso_week(&self) -> u32 {
+        self.0.iso_week().week()
+    }
+
+    /// The year the ISO week belongs to, which differs from the calendar year around new year
+    fn iso_year(&self) -> i32 {
+        self.0.iso_week().year()
+    }
+
+    fn day_of_year(&self) -> u32 {
+        self.0.ordinal()
+    }
+
+    fn days_in_month(&self) -> u32 {
+        days_in_month(self.0.year(), self.0.month()).unwrap()
+    }
+
+    /// The date at `time` in the offset of the time
+    fn on(&self, date: NaiveDate, time: chrono::NaiveTime) -> DateTime {
+        DateTime(self.0.offset().from_local_datetime(&date.and_time(time)).unwrap())
+    }
+
+    fn start_of_day(&self) -> DateTime {
+        self.on(self.0.date().naive_local(), chrono::NaiveTime::from_hms(0, 0, 0))
+    }
+
+    /// Midnight of the Monday of the week
+    fn start_of_week(&self) -> DateTime {
+        self.on(
+            start_of_week(self.0.date().naive_local()),
+            chrono::NaiveTime::from_hms(0, 0, 0),
+        )
+    }
+
+    fn start_of_month(&self) -> DateTime {
+        self.on(
+            self.0.date().naive_local().with_day(1).unwrap(),
+            chrono::NaiveTime::from_hms(0, 0, 0),
+        )
+    }
+
+    /// Move by calendar months, keeping the time of day. Days past the end of the month are clamped to its last
+    /// day
+    fn add_months(&self, months: i32) -> Option<DateTime> {
+        let date = add_months(self.0.date().naive_local(), months)?;
+        Some(self.on(date, self.0.time()))
+    }
+
+    fn add_years(&self, years: i32) -> Option<DateTime> {
+        self.add_months(years.checked_mul(12)?)
+    }
+}
+
+/// A calendar date without a time or timezone, for all-day events
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Trace, VmType, Userdata)]
+#[gluon_userdata(clone)]
+#[gluon(vm_type = "time.Date")]
+#[gluon_trace(skip)]
+pub struct Date(pub chrono::NaiveDate);
+
+impl<'vm, 'value> Getable<'vm, 'value> for Date {
+    type Proxy = Variants<'value>;
+    fn to_proxy(_vm: &'vm Thread, value: Variants<'value>) -> GluonResult<Self::Proxy> {
+        Ok(value)
+    }
+    fn from_proxy(vm: &'vm Thread, proxy: &'value mut Self::Proxy) -> Self {
+        <Self as Getable<'vm, 'value>>::from_value(vm, proxy.clone())
+    }
+    fn from_value(vm: &'vm Thread, value: Variants<'value>) -> Self {
+        *<&'value Date as Getable<'vm, 'value>>::from_value(vm, value)
+    }
+}
+
+/// Dates are stored as `YYYY-MM-DD`, so that they can't be mistaken for timestamps
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.format("%Y-%m-%d").to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DateVisitor;
+        use std::fmt;
+        impl<'de> de::Visitor<'de> for DateVisitor {
+            type Value = Date;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("YYYY-MM-DD Date")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map(Date)
+                    .map_err(E::custom)
+            }
+        }
+        deserializer.deserialize_str(DateVisitor)
+    }
+}
+
+impl Date {
+    fn new(y: i32, m: u32, d: u32) -> Option<Date> {
+        chrono::NaiveDate::from_ymd_opt(y, m, d).map(Date)
+    }
+
+    /// The local date of `t`
+    pub fn of(t: &DateTime) -> Date {
+        Date(t.0.with_timezone(&LocalZone).date().naive_local())
+    }
+
+    fn today(_: ()) -> Date {
+        Date(clock::local_now().date().naive_local())
+    }
+
+    fn year(&self) -> i32 {
+        self.0.year()
+    }
+
+    fn month(&self) -> u32 {
+        self.0.month()
+    }
+
+    fn day(&self) -> u32 {
+        self.0.day()
+    }
+
+    fn add_days(&self, days: i64) -> Date {
+        Date(self.0 + chrono::Duration::days(days))
+    }
+
+    /// Local midnight at the start of the date. Skipped midnights fall back to the first time of the date
+    pub fn start(&self) -> DateTime {
+        let mut t = self.0.and_hms(0, 0, 0);
+        loop {
+            if let Some(start) = LocalZone.from_local_datetime(&t).earliest() {
+                return start.into();
+            }
+            t += chrono::Duration::minutes(30);
+        }
+    }
+
+    fn format(&self, format: &str) -> String {
+        self.0.format(format).to_string()
+    }
+
+    fn eq(&self, b: &Date) -> bool {
+        self.0 == b.0
+    }
+
+    fn lt(&self, b: &Date) -> bool {
+        self.0 < b.0
+    }
+
+    fn weekday(&self) -> u32 {
+        self.0.weekday().num_days_from_monday()
+    }
+
+    fn iso_week(&self) -> u32 {
+        self.0.iso_week().week()
+    }
+
+    fn iso_year(&self) -> i32 {
+        self.0.iso_week().year()
+    }
+
+    fn day_of_year(&self) -> u32 {
+        self.0.ordinal()
+    }
+
+    fn days_in_month(&self) -> u32 {
+        days_in_month(self.0.year(), self.0.month()).unwrap()
+    }
+
+    fn start_of_week(&self) -> Date {
+        Date(start_of_week(self.0))
+    }
+
+    fn start_of_month(&self) -> Date {
+        Date(self.0.with_day(1).unwrap())
+    }
+
+    fn add_months(&self, months: i32) -> Option<Date> {
+        add_months(self.0, months).map(Date)
+    }
+
+    fn add_years(&self, years: i32) -> Option<Date> {
+        add_months(self.0, years.checked_mul(12)?).map(Date)
+    }
+
+    /// Number of days from `b` to the date
+    fn sub(&self, b: &Date) -> i64 {
+        self.0.signed_duration_since(b.0).num_days()
+    }
 }
 
 #[derive(Clone, Copy, Debug, Userdata, Trace, VmType)]
@@ -148,6 +467,12 @@ impl DateTime {
 #[gluon_trace(skip)]
 pub struct Duration(pub chrono::Duration);
 
+impl Default for Duration {
+    fn default() -> Duration {
+        Duration(chrono::Duration::zero())
+    }
+}
+
 impl From<chrono::Duration> for Duration {
     fn from(d: chrono::Duration) -> Duration {
         Duration(d)
@@ -167,7 +492,7 @@ impl<'vm, 'value> Getable<'vm, 'value> for Duration {
     }
 }
 
-// We only care about seconds, so we can just discard the nanos
+/// Durations are stored as whole seconds by default, dropping the fraction. Use `millis` to keep milliseconds
 impl Serialize for Duration {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         serializer.serialize_i64(self.0.num_seconds())
@@ -176,20 +501,8 @@ impl Serialize for Duration {
 
 impl<'de> Deserialize<'de> for Duration {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct DurationVisitor;
-        use std::fmt;
-        impl<'de> de::Visitor<'de> for DurationVisitor {
-            type Value = Duration;
-
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("Duration")
-            }
-
-            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
-                Ok(Duration(chrono::Duration::seconds(value as i64)))
-            }
-        }
-        deserializer.deserialize_u64(DurationVisitor)
+        let ms = deserializer.deserialize_any(MillisVisitor("Duration"))?;
+        Ok(Duration(chrono::Duration::milliseconds(ms)))
     }
 }
 
@@ -218,6 +531,11 @@ impl Duration {
         Duration(chrono::Duration::weeks(s))
     }
 
+    /// Parse durations like `1h30m`, `2w` or `1 hour 30 minutes`
+    pub fn parse(s: &str) -> Result<Duration, String> {
+        human::parse_duration(s).map(Duration).map_err(|e| e.to_string())
+    }
+
     fn eq(&self, b: &Duration) -> bool {
         self.0 == b.0
     }
@@ -258,31 +576,66 @@ impl Duration {
 #[gluon_userdata(clone)]
 #[gluon(vm_type = "time.TimeZone")]
 #[gluon_trace(skip)]
-pub struct TimeZone(pub chrono::FixedOffset);
+pub enum TimeZone {
+    Fixed(FixedOffset),
+    /// The system timezone or the one picked with `set_local`, following its rules (like DST) for each time
+    /// converted
+    Local,
+}
 
 impl TimeZone {
     fn east(secs: i32) -> TimeZone {
-        TimeZone(FixedOffset::east(secs))
+        TimeZone::Fixed(FixedOffset::east(secs))
     }
 
     fn west(secs: i32) -> TimeZone {
-        TimeZone(FixedOffset::west(secs))
+        TimeZone::Fixed(FixedOffset::west(secs))
+    }
+
+    /// Offset from UTC in seconds at the time
+    fn offset_at(&self, t: &DateTime) -> i32 {
+        t.with_timezone(self).0.offset().local_minus_utc()
+    }
+
+    /// Go back to the system timezone, re-reading it in case `/etc/localtime` changed
+    fn reload(_: ()) -> IO<()> {
+        clock::set_zone(None);
+        unsafe { libc::tzset() };
+        IO::Value(())
+    }
+
+    /// Use the timezone named like `Europe/Paris` as the local one. Empty to go back to the system timezone
+    fn set_local(name: &str) -> IO<()> {
+        if name.is_empty() {
+            return TimeZone::reload(());
+        }
+        match name.parse() {
+            Ok(tz) => {
+                clock::set_zone(Some(tz));
+                IO::Value(())
+            }
+            Err(_) => IO::Exception(format!("Unknown timezone '{}'", name)),
+        }
     }
 }
 
 pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
     thread.register_type::<DateTime>("time.DateTime", &[])?;
     thread.register_type::<Duration>("time.Duration", &[])?;
+    thread.register_type::<Date>("time.Date", &[])?;
     thread.register_type::<TimeZone>("time.TimeZone", &[])?;
     ExternModule::new(
         thread,
         record! {
             timezone => record! {
                 type TimeZone => TimeZone,
-                utc => TimeZone(Utc.fix()),
-                local => TimeZone(*Local::now().offset()),
+                utc => TimeZone::Fixed(Utc.fix()),
+                local => TimeZone::Local,
                 east => primitive!(1, TimeZone::east),
                 west => primitive!(1, TimeZone::west),
+                offset_at => primitive!(2, TimeZone::offset_at),
+                reload => primitive!(1, TimeZone::reload),
+                set_local => primitive!(1, TimeZone::set_local),
             },
             datetime => record! {
                 type DateTime => DateTime,
@@ -306,10 +659,45 @@ pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
                 to_local => primitive!(1, DateTime::to_local),
                 local_now => primitive!(1, DateTime::local_now),
                 utc_now => primitive!(1, DateTime::utc_now),
+                parse_human => primitive!(3, DateTime::parse_human),
                 sub => primitive!(2, DateTime::sub),
                 add => primitive!(2, DateTime::add),
                 eq => primitive!(2, DateTime::eq),
                 lt => primitive!(2, DateTime::lt),
+                weekday => primitive!(1, DateTime::weekday),
+                iso_week => primitive!(1, DateTime::iso_week),
+                iso_year => primitive!(1, DateTime::iso_year),
+                day_of_year => primitive!(1, DateTime::day_of_year),
+                days_in_month => primitive!(1, DateTime::days_in_month),
+                start_of_day => primitive!(1, DateTime::start_of_day),
+                start_of_week => primitive!(1, DateTime::start_of_week),
+                start_of_month => primitive!(1, DateTime::start_of_month),
+                add_months => primitive!(2, DateTime::add_months),
+                add_years => primitive!(2, DateTime::add_years),
+            },
+            date => record! {
+                type Date => Date,
+                new => primitive!(3, Date::new),
+                of => primitive!(1, Date::of),
+                today => primitive!(1, Date::today),
+                year => primitive!(1, Date::year),
+                month => primitive!(1, Date::month),
+                day => primitive!(1, Date::day),
+                add_days => primitive!(2, Date::add_days),
+                start => primitive!(1, Date::start),
+                format => primitive!(2, Date::format),
+                eq => primitive!(2, Date::eq),
+                lt => primitive!(2, Date::lt),
+                weekday => primitive!(1, Date::weekday),
+                iso_week => primitive!(1, Date::iso_week),
+                iso_year => primitive!(1, Date::iso_year),
+                day_of_year => primitive!(1, Date::day_of_year),
+                days_in_month => primitive!(1, Date::days_in_month),
+                start_of_week => primitive!(1, Date::start_of_week),
+                start_of_month => primitive!(1, Date::start_of_month),
+                add_months => primitive!(2, Date::add_months),
+                add_years => primitive!(2, Date::add_years),
+                sub => primitive!(2, Date::sub),
             },
             duration => record! {
                 type Duration => Duration,
@@ -319,6 +707,7 @@ pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
                 hours => primitive!(1, Duration::hours),
                 days => primitive!(1, Duration::days),
                 weeks => primitive!(1, Duration::weeks),
+                parse => primitive!(1, Duration::parse),
                 eq => primitive!(2, Duration::eq),
                 lt => primitive!(2, Duration::lt),
                 to_secs => primitive!(1, |d: Duration| d.num_seconds()),
@@ -328,3 +717,107 @@ pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
         },
     )
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone as _;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Precise {
+        #[serde(with = "super::millis")]
+        time: DateTime,
+        #[serde(with = "super::millis")]
+        dur: Duration,
+    }
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_local() {
+        // Each time gets the offset in effect then, in summer and winter alike
+        for t in &[
+            Utc.ymd(2021, 1, 15).and_hms(12, 0, 0),
+            Utc.ymd(2021, 7, 15).and_hms(12, 0, 0),
+        ] {
+            let offset = Local.offset_from_utc_datetime(&t.naive_utc()).fix();
+            let local = DateTime::from(*t).to_local();
+            assert_eq!(*local.0.offset(), offset);
+            assert_eq!(local, DateTime::from(*t));
+            assert_eq!(DateTime::from(*t).with_timezone(&TimeZone::Local), local);
+            assert_eq!(TimeZone::Local.offset_at(&local), offset.local_minus_utc());
+        }
+        assert_eq!(TimeZone::east(3600).offset_at(&Utc::now().into()), 3600);
+    }
+
+    #[test]
+    fn test_calendar() {
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        assert_eq!(days_in_month(2020, 2), Some(29));
+        assert_eq!(days_in_month(2021, 2), Some(28));
+        assert_eq!(days_in_month(2021, 12), Some(31));
+        assert_eq!(days_in_month(2021, 13), None);
+        assert_eq!(add_months(date(2021, 1, 31), 1), Some(date(2021, 2, 28)));
+        assert_eq!(add_months(date(2020, 2, 29), 12), Some(date(2021, 2, 28)));
+        assert_eq!(add_months(date(2021, 3, 31), -13), Some(date(2020, 2, 29)));
+        assert_eq!(add_months(date(2021, 11, 15), 2), Some(date(2022, 1, 15)));
+        assert_eq!(start_of_week(date(2021, 3, 14)), date(2021, 3, 8));
+        assert_eq!(start_of_week(date(2021, 3, 8)), date(2021, 3, 8));
+
+        let t = DateTime(FixedOffset::east(3600).ymd(2021, 1, 31).and_hms(10, 30, 0));
+        assert_eq!(
+            t.add_months(1).unwrap().0,
+            FixedOffset::east(3600).ymd(2021, 2, 28).and_hms(10, 30, 0)
+        );
+        assert_eq!(
+            t.start_of_month().0,
+            FixedOffset::east(3600).ymd(2021, 1, 1).and_hms(0, 0, 0)
+        );
+        assert_eq!(t.weekday(), 6);
+        assert_eq!((t.iso_year(), t.iso_week()), (2021, 4));
+        assert_eq!(t.day_of_year(), 31);
+    }
+
+    #[test]
+    fn test_serde() {
+        let t: DateTime = serde_json::from_str("-86400").unwrap();
+        assert_eq!(t.0, Utc.ymd(1969, 12, 31).and_hms(0, 0, 0));
+        let d: Duration = serde_json::from_str("-90").unwrap();
+        assert_eq!(d.0, chrono::Duration::seconds(-90));
+        let d: Duration = serde_json::from_str("1.5").unwrap();
+        assert_eq!(d.0, chrono::Duration::milliseconds(1500));
+        let d: Duration = serde_json::from_str(r#"{"ms": -1500}"#).unwrap();
+        assert_eq!(d.0, chrono::Duration::milliseconds(-1500));
+        assert!(serde_json::from_str::<DateTime>(r#""2021-01-01""#).is_err());
+        assert!(serde_json::from_str::<DateTime>(r#"{"s": 1}"#).is_err());
+        assert!(serde_json::from_str::<DateTime>("18446744073709551615").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_seconds_round_trip(secs in -50_000_000_000i64..50_000_000_000, dur in i64::MIN / 1000..i64::MAX / 1000) {
+            let t = DateTime::from(Utc.timestamp(secs, 0));
+            prop_assert_eq!(round_trip(&t), t);
+            let d = Duration(chrono::Duration::seconds(dur));
+            prop_assert_eq!(round_trip(&d).0, d.0);
+        }
+
+        #[test]
+        fn prop_millis_round_trip(ms in -1_000_000_000_000_000i64..1_000_000_000_000_000, dur in i64::MIN + 1..=i64::MAX) {
+            let precise = Precise {
+                time: DateTime::from(Utc.timestamp_millis(ms)),
+                dur: Duration(chrono::Duration::milliseconds(dur)),
+            };
+            let back = round_trip(&precise);
+            prop_assert_eq!(back.time, precise.time);
+            prop_assert_eq!(back.dur.0, precise.dur.0);
+            // Readers of the default encoding also take milliseconds
+            let t: DateTime = serde_json::from_value(serde_json::json!({ "ms": ms })).unwrap();
+            prop_assert_eq!(t, precise.time);
+        }
+    }
+}
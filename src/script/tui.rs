@@ -1,4 +1,26 @@
-use gluon::{vm::ExternModule, Thread};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use gluon::{
+    vm::{
+        api::{OwnedFunction, IO},
+        ExternModule,
+    },
+    Thread,
+};
+use termion::{
+    clear, color, cursor,
+    event::{parse_event, Event, Key},
+    raw::IntoRawMode,
+    screen::AlternateScreen,
+    style, terminal_size,
+};
+
+use crate::{script::sched::lock_store, util::fit};
 
 #[derive(Clone, Debug, VmType, Pushable, Getable)]
 enum Color {
@@ -22,8 +44,6 @@ enum Color {
     LightWhite,
 }
 
-use termion::{color, style};
-
 fn fg(c: Color) -> String {
     match c {
         Color::Reset => color::Reset.fg_str().to_string(),
@@ -70,10 +90,565 @@ fn bg(c: Color) -> String {
     }
 }
 
+/// How often a running screen is redrawn without any input or logs, so that times shown stay current
+const REFRESH: Duration = Duration::from_secs(30);
+/// How long to wait for input before checking for new logs, in milliseconds
+const POLL_MS: i32 = 200;
+
+/// Whether a log was created since the screen was last drawn
+static DIRTY: AtomicBool = AtomicBool::new(false);
+/// Whether the handler setting `DIRTY` was added
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Dir {
+    Row,
+    Column,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Text {
+        lines: Vec<String>,
+    },
+    List {
+        items: Vec<String>,
+        selected: Option<usize>,
+    },
+    Table {
+        header: Vec<String>,
+        rows: Vec<Vec<String>>,
+        selected: Option<usize>,
+    },
+    Input {
+        label: String,
+        value: String,
+        focused: bool,
+    },
+    Border {
+        title: String,
+        child: Box<Node>,
+    },
+    Styled {
+        style: String,
+        child: Box<Node>,
+    },
+    /// A child of a layout with a fixed size along the layout direction
+    Sized {
+        size: u16,
+        child: Box<Node>,
+    },
+    Layout {
+        dir: Dir,
+        children: Vec<Node>,
+    },
+}
+
+#[derive(Clone, Debug, Userdata, Trace, VmType)]
+#[gluon_userdata(clone)]
+#[gluon(vm_type = "tui.Widget")]
+#[gluon_trace(skip)]
+struct Widget(Node);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cell {
+    ch: char,
+    style: String,
+}
+
+/// The contents of the terminal, kept to only redraw what changed
+#[derive(Clone, Debug, PartialEq)]
+struct Buffer {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rect {
+    x: u16,
+    y: u16,
+    w: u16,
+    h: u16,
+}
+
+impl Buffer {
+    fn new(width: u16, height: u16) -> Buffer {
+        let blank = Cell {
+            ch: ' ',
+            style: String::new(),
+        };
+        Buffer {
+            width,
+            height,
+            cells: vec![blank; width as usize * height as usize],
+        }
+    }
+
+    /// Write `s` from `(x, y)`, cutting it at the edge of the buffer
+    fn put(&mut self, x: u16, y: u16, s: &str, style: &str) {
+        if y >= self.height {
+            return;
+        }
+        for (x, ch) in (x..self.width).zip(s.chars()) {
+            let i = y as usize * self.width as usize + x as usize;
+            self.cells[i] = Cell {
+                ch,
+                style: style.into(),
+            };
+        }
+    }
+
+    /// Write the cells which differ from `prev`, or all of them if the size changed
+    fn diff<W: Write>(&self, prev: Option<&Buffer>, out: &mut W) -> std::io::Result<()> {
+        let prev = prev.filter(|p| p.width == self.width && p.height == self.height);
+        if prev.is_none() {
+            write!(out, "{}", clear::All)?;
+        }
+        let mut style = None;
+        let mut pos = None;
+        for (i, cell) in self.cells.iter().enumerate() {
+            if prev.map_or(false, |p| p.cells[i] == *cell) {
+                continue;
+            }
+            let (x, y) = ((i % self.width as usize) as u16, (i / self.width as usize) as u16);
+            if pos != Some((x, y)) {
+                write!(out, "{}", cursor::Goto(x + 1, y + 1))?;
+            }
+            if style != Some(&cell.style) {
+                write!(out, "{}{}{}", color::Fg(color::Reset), style::Reset, cell.style)?;
+                style = Some(&cell.style);
+            }
+            write!(out, "{}", cell.ch)?;
+            pos = Some((x + 1, y));
+        }
+        write!(out, "{}{}", color::Fg(color::Reset), style::Reset)?;
+        out.flush()
+    }
+}
+
+/// Sizes of the children of a layout along its direction. Children with fixed sizes get them first, and the
+/// rest of the space is shared equally by the others
+fn split(total: u16, sizes: &[Option<u16>]) -> Vec<u16> {
+    let fixed = sizes.iter().flatten().fold(0u16, |acc, &s| acc.saturating_add(s));
+    let flexible = sizes.iter().filter(|s| s.is_none()).count() as u16;
+    let free = total.saturating_sub(fixed);
+    let (share, mut extra) = match flexible {
+        0 => (0, 0),
+        n => (free / n, free % n),
+    };
+    let mut left = total;
+    sizes
+        .iter()
+        .map(|s| {
+            let want = s.unwrap_or_else(|| {
+                if extra > 0 {
+                    extra -= 1;
+                    share + 1
+                } else {
+                    share
+                }
+            });
+            let size = want.min(left);
+            left -= size;
+            size
+        })
+        .collect()
+}
+
+/// Draw lines, inverting the selected one and scrolling so that it's visible
+fn render_lines(buf: &mut Buffer, area: Rect, style: &str, lines: &[String], selected: Option<usize>) {
+    let height = area.h as usize;
+    let offset = selected.map_or(0, |s| (s + 1).saturating_sub(height));
+    for (i, line) in lines.iter().enumerate().skip(offset).take(height) {
+        let style = if selected == Some(i) {
+            format!("{}{}", style, style::Invert)
+        } else {
+            style.to_string()
+        };
+        buf.put(
+            area.x,
+            area.y + (i - offset) as u16,
+            &fit(line, area.w as usize),
+            &style,
+        );
+    }
+}
+
+fn render(node: &Node, buf: &mut Buffer, area: Rect, style: &str) {
+    if area.w == 0 || area.h == 0 {
+        return;
+    }
+    let width = area.w as usize;
+    match node {
+        Node::Text { lines } => render_lines(buf, area, style, lines, None),
+        Node::List { items, selected } => render_lines(buf, area, style, items, *selected),
+        Node::Table { header, rows, selected } => {
+            let columns = rows.iter().map(Vec::len).fold(header.len(), usize::max);
+            let widths = (0..columns)
+                .map(|c| {
+                    iter_rows(header, rows)
+                        .filter_map(|r| r.get(c))
+                        .map(|s| s.chars().count())
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect::<Vec<_>>();
+            let line = |cells: &[String]| {
+                widths
+                    .iter()
+                    .enumerate()
+                    .map(|(c, &w)| fit(cells.get(c).map_or("", String::as_str), w))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            };
+            let bold = format!("{}{}", style, style::Bold);
+            buf.put(area.x, area.y, &fit(&line(header), width), &bold);
+            let lines = rows.iter().map(|r| line(r)).collect::<Vec<_>>();
+            let body = Rect {
+                y: area.y + 1,
+                h: area.h - 1,
+                ..area
+            };
+            render_lines(buf, body, style, &lines, *selected);
+        }
+        Node::Input { label, value, focused } => {
+            let text = format!("{}{}", label, value);
+            buf.put(area.x, area.y, &fit(&text, width), style);
+            let end = text.chars().count();
+            if *focused && end < width {
+                buf.put(area.x + end as u16, area.y, " ", &format!("{}{}", style, style::Invert));
+            }
+        }
+        Node::Border { title, child } => {
+            if area.w < 2 || area.h < 2 {
+                return;
+            }
+            let inner = width - 2;
+            let title = if title.is_empty() {
+                String::new()
+            } else {
+                fit(&format!(" {} ", title), inner.min(title.chars().count() + 2))
+            };
+            let rule = "─".repeat(inner - title.chars().count());
+            buf.put(area.x, area.y, &format!("┌{}{}┐", title, rule), style);
+            for y in area.y + 1..area.y + area.h - 1 {
+                buf.put(area.x, y, "│", style);
+                buf.put(area.x + area.w - 1, y, "│", style);
+            }
+            buf.put(area.x, area.y + area.h - 1, &format!("└{}┘", "─".repeat(inner)), style);
+            let inside = Rect {
+                x: area.x + 1,
+                y: area.y + 1,
+                w: area.w - 2,
+                h: area.h - 2,
+            };
+            render(child, buf, inside, style);
+        }
+        Node::Styled { style: inner, child } => render(child, buf, area, &format!("{}{}", style, inner)),
+        Node::Sized { child, .. } => render(child, buf, area, style),
+        Node::Layout { dir, children } => {
+            let total = match dir {
+                Dir::Row => area.w,
+                Dir::Column => area.h,
+            };
+            let sizes = children
+                .iter()
+                .map(|c| match c {
+                    Node::Sized { size, .. } => Some(*size),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let mut offset = 0;
+            for (child, size) in children.iter().zip(split(total, &sizes)) {
+                let rect = match dir {
+                    Dir::Row => Rect {
+                        x: area.x + offset,
+                        w: size,
+                        ..area
+                    },
+                    Dir::Column => Rect {
+                        y: area.y + offset,
+                        h: size,
+                        ..area
+                    },
+                };
+                render(child, buf, rect, style);
+                offset += size;
+            }
+        }
+    }
+}
+
+fn iter_rows<'a>(header: &'a [String], rows: &'a [Vec<String>]) -> impl Iterator<Item = &'a [String]> {
+    std::iter::once(header).chain(rows.iter().map(Vec::as_slice))
+}
+
+/// Name of a key as used by `bind` and passed to `on_input`
+fn key_name(key: Key) -> Option<String> {
+    let name = match key {
+        Key::Char('\n') => "enter".into(),
+        Key::Char('\t') => "tab".into(),
+        Key::Char(c) => c.to_string(),
+        Key::Alt(c) => format!("alt-{}", c),
+        Key::Ctrl(c) => format!("ctrl-{}", c),
+        Key::F(n) => format!("f{}", n),
+        Key::Backspace => "backspace".into(),
+        Key::Delete => "delete".into(),
+        Key::Insert => "insert".into(),
+        Key::Esc => "esc".into(),
+        Key::BackTab => "backtab".into(),
+        Key::Left => "left".into(),
+        Key::Right => "right".into(),
+        Key::Up => "up".into(),
+        Key::Down => "down".into(),
+        Key::Home => "home".into(),
+        Key::End => "end".into(),
+        Key::PageUp => "pageup".into(),
+        Key::PageDown => "pagedown".into(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Wait up to `timeout` milliseconds for input and read the keys available. Stdin is only read when there's
+/// input, so that no key is taken from whatever reads the terminal after the screen is closed
+fn read_keys(timeout: i32) -> Vec<Key> {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut fd, 1, timeout) } <= 0 {
+        return Vec::new();
+    }
+    let mut bytes = [0u8; 64];
+    let n = unsafe { libc::read(libc::STDIN_FILENO, bytes.as_mut_ptr() as *mut libc::c_void, bytes.len()) };
+    if n <= 0 {
+        return Vec::new();
+    }
+    let mut iter = bytes[..n as usize].iter().map(|&b| Ok(b));
+    let mut keys = Vec::new();
+    while let Some(Ok(b)) = iter.next() {
+        if let Ok(Event::Key(key)) = parse_event(b, &mut iter) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+type ViewFunc = OwnedFunction<fn(()) -> IO<Widget>>;
+type Action = OwnedFunction<fn(()) -> IO<()>>;
+type InputFunc = OwnedFunction<fn(String) -> IO<()>>;
+
+#[derive(Default)]
+struct Handlers {
+    bindings: HashMap<String, Action>,
+    /// While set, gets all keys instead of the bindings, for text inputs
+    input: Option<InputFunc>,
+}
+
+#[derive(Userdata, Trace, VmType)]
+#[gluon(vm_type = "tui.Screen")]
+#[gluon_trace(skip)]
+struct Screen {
+    view: ViewFunc,
+    handlers: Mutex<Handlers>,
+    quit: AtomicBool,
+}
+
+impl fmt::Debug for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Screen(..)")
+    }
+}
+
+fn run_io<T, E: fmt::Display>(res: Result<IO<T>, E>) -> Result<T, String> {
+    match res {
+        Ok(IO::Value(v)) => Ok(v),
+        Ok(IO::Exception(e)) => Err(e),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn text(s: String) -> Widget {
+    Widget(Node::Text {
+        lines: s.lines().map(String::from).collect(),
+    })
+}
+
+fn list(items: Vec<String>, selected: Option<usize>) -> Widget {
+    Widget(Node::List { items, selected })
+}
+
+fn table(header: Vec<String>, rows: Vec<Vec<String>>, selected: Option<usize>) -> Widget {
+    Widget(Node::Table { header, rows, selected })
+}
+
+fn input(label: String, value: String, focused: bool) -> Widget {
+    Widget(Node::Input { label, value, focused })
+}
+
+fn border(title: String, child: Widget) -> Widget {
+    Widget(Node::Border {
+        title,
+        child: Box::new(child.0),
+    })
+}
+
+fn styled(style: String, child: Widget) -> Widget {
+    Widget(Node::Styled {
+        style,
+        child: Box::new(child.0),
+    })
+}
+
+fn sized(size: u16, child: Widget) -> Widget {
+    Widget(Node::Sized {
+        size,
+        child: Box::new(child.0),
+    })
+}
+
+fn row(children: Vec<Widget>) -> Widget {
+    Widget(Node::Layout {
+        dir: Dir::Row,
+        children: children.into_iter().map(|c| c.0).collect(),
+    })
+}
+
+fn column(children: Vec<Widget>) -> Widget {
+    Widget(Node::Layout {
+        dir: Dir::Column,
+        children: children.into_iter().map(|c| c.0).collect(),
+    })
+}
+
+/// Apply a key passed to `on_input` to the value of a text input
+fn edit(value: String, key: String) -> String {
+    let mut value = value;
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => value.push(c),
+        _ if key == "backspace" => {
+            value.pop();
+        }
+        _ if key == "ctrl-u" => value.clear(),
+        _ if key == "ctrl-w" => {
+            let end = value.trim_end().rfind(' ').map_or(0, |i| i + 1);
+            value.truncate(end);
+        }
+        _ => (),
+    }
+    value
+}
+
+fn screen(view: ViewFunc) -> IO<Screen> {
+    IO::Value(Screen {
+        view,
+        handlers: Mutex::new(Handlers::default()),
+        quit: AtomicBool::new(false),
+    })
+}
+
+fn bind(screen: &Screen, key: String, action: Action) -> IO<()> {
+    screen.handlers.lock().unwrap().bindings.insert(key, action);
+    IO::Value(())
+}
+
+fn on_input(screen: &Screen, func: Option<InputFunc>) -> IO<()> {
+    screen.handlers.lock().unwrap().input = func;
+    IO::Value(())
+}
+
+fn quit(screen: &Screen) -> IO<()> {
+    screen.quit.store(true, Ordering::SeqCst);
+    IO::Value(())
+}
+
+fn handle_key(screen: &Screen, name: String) -> Result<(), String> {
+    // The handlers may change the bindings, so don't hold the lock while calling them
+    let (action, input) = {
+        let handlers = screen.handlers.lock().unwrap();
+        (handlers.bindings.get(&name).cloned(), handlers.input.clone())
+    };
+    match (input, action) {
+        _ if name == "ctrl-c" => screen.quit.store(true, Ordering::SeqCst),
+        (Some(mut input), _) => return run_io(input.call(name)),
+        (None, Some(mut action)) => return run_io(action.call(())),
+        (None, None) if name == "q" => screen.quit.store(true, Ordering::SeqCst),
+        (None, None) => (),
+    }
+    Ok(())
+}
+
+fn screen_loop<W: Write>(screen: &Screen, out: &mut W) -> Result<(), String> {
+    let mut prev: Option<Buffer> = None;
+    let mut drawn = Instant::now();
+    let mut redraw = true;
+    write!(out, "{}", cursor::Hide).map_err(|e| e.to_string())?;
+    while !screen.quit.load(Ordering::SeqCst) {
+        let (width, height) = terminal_size().map_err(|e| e.to_string())?;
+        let resized = prev.as_ref().map_or(true, |p| (p.width, p.height) != (width, height));
+        if redraw || resized || DIRTY.swap(false, Ordering::SeqCst) || drawn.elapsed() >= REFRESH {
+            let widget = run_io(screen.view.clone().call(()))?;
+            let mut buf = Buffer::new(width, height);
+            let area = Rect {
+                x: 0,
+                y: 0,
+                w: width,
+                h: height,
+            };
+            render(&widget.0, &mut buf, area, "");
+            buf.diff(prev.as_ref(), out).map_err(|e| e.to_string())?;
+            prev = Some(buf);
+            drawn = Instant::now();
+            redraw = false;
+        }
+        for key in read_keys(POLL_MS) {
+            if let Some(name) = key_name(key) {
+                handle_key(screen, name)?;
+                redraw = true;
+            }
+            if screen.quit.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_screen(screen: &Screen) -> Result<(), String> {
+    if !WATCHING.load(Ordering::SeqCst) {
+        lock_store()
+            .and_then(|mut s| s.add_native(".*", Box::new(|_| DIRTY.store(true, Ordering::SeqCst))))
+            .map_err(|e| e.to_string())?;
+        WATCHING.store(true, Ordering::SeqCst);
+    }
+    screen.quit.store(false, Ordering::SeqCst);
+    let mut out = AlternateScreen::from(stdout().into_raw_mode().map_err(|e| e.to_string())?);
+    let res = screen_loop(screen, &mut out);
+    let _ = write!(out, "{}", cursor::Show).and_then(|_| out.flush());
+    res
+}
+
+/// Show the screen until `quit` is called, `ctrl-c` is pressed, or `q` is pressed while not bound. The view is
+/// rebuilt after every key and every new log
+fn run(screen: &Screen) -> IO<()> {
+    match run_screen(screen) {
+        Ok(()) => IO::Value(()),
+        Err(e) => IO::Exception(e),
+    }
+}
+
 pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
+    thread.register_type::<Widget>("tui.Widget", &[])?;
+    thread.register_type::<Screen>("tui.Screen", &[])?;
     ExternModule::new(
         thread,
         record! {
+            type Widget => Widget,
+            type Screen => Screen,
             fg => primitive!(1, fg),
             bg => primitive!(1, bg),
             rgb => primitive!(3, Color::Rgb),
@@ -101,6 +676,79 @@ pub fn load(thread: &Thread) -> Result<ExternModule, gluon::vm::Error> {
             no_bold => style::NoBold.to_string(),
             no_italic => style::NoItalic.to_string(),
             no_underline => style::NoUnderline.to_string(),
+            text => primitive!(1, text),
+            list => primitive!(2, list),
+            table => primitive!(3, table),
+            input => primitive!(3, input),
+            border => primitive!(2, border),
+            styled => primitive!(2, styled),
+            sized => primitive!(2, sized),
+            row => primitive!(1, row),
+            column => primitive!(1, column),
+            edit => primitive!(2, edit),
+            screen => primitive!(1, screen),
+            bind => primitive!(3, bind),
+            on_input => primitive!(2, on_input),
+            quit => primitive!(1, quit),
+            run => primitive!(1, run),
         },
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(buf: &Buffer, y: u16) -> String {
+        let start = y as usize * buf.width as usize;
+        buf.cells[start..start + buf.width as usize]
+            .iter()
+            .map(|c| c.ch)
+            .collect()
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(10, &[Some(3), None, None]), vec![3, 4, 3]);
+        assert_eq!(split(5, &[Some(3), Some(4), None]), vec![3, 2, 0]);
+        assert_eq!(split(7, &[None, None, None]), vec![3, 2, 2]);
+    }
+
+    #[test]
+    fn test_render() {
+        let widget = column(vec![
+            sized(3, border("ab".into(), text("hello world".into()))),
+            table(
+                vec!["id".into(), "name".into()],
+                vec![vec!["1".into(), "x".into()], vec!["22".into(), "y".into()]],
+                Some(1),
+            ),
+        ]);
+        let mut buf = Buffer::new(8, 6);
+        let area = Rect { x: 0, y: 0, w: 8, h: 6 };
+        render(&widget.0, &mut buf, area, "");
+        assert_eq!(line(&buf, 0), "┌ ab ──┐");
+        assert_eq!(line(&buf, 1), "│hello…│");
+        assert_eq!(line(&buf, 2), "└──────┘");
+        assert_eq!(line(&buf, 3), "id  name");
+        assert_eq!(line(&buf, 4), "1   x   ");
+        assert_eq!(line(&buf, 5), "22  y   ");
+        assert!(buf.cells[5 * 8].style.contains(&style::Invert.to_string()));
+
+        let mut out = Vec::new();
+        let mut next = buf.clone();
+        next.put(0, 5, "33", "");
+        next.diff(Some(&buf), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&cursor::Goto(1, 6).to_string()));
+        assert!(!out.contains("id"));
+    }
+
+    #[test]
+    fn test_edit() {
+        assert_eq!(edit("ab".into(), "c".into()), "abc");
+        assert_eq!(edit("ab".into(), "backspace".into()), "a");
+        assert_eq!(edit("ab cd".into(), "ctrl-w".into()), "ab ");
+        assert_eq!(edit("ab".into(), "up".into()), "ab");
+    }
+}
@@ -6,10 +6,17 @@ use crate::storage::{Error, Result};
 use crate::util::print_gluon_err;
 
 pub type SignalHandler = OwnedFunction<fn(Log) -> IO<()>>;
+/// Handlers on the Rust side. They are run with the store locked, so they shouldn't touch the store
+pub type NativeHandler = Box<dyn FnMut(&Log) + Send>;
+
+enum Handler {
+    Gluon(SignalHandler),
+    Native(NativeHandler),
+}
 
 pub struct SignalHandlerEntry {
     pat: Regex,
-    func: SignalHandler,
+    func: Handler,
 }
 
 pub struct SignalHandlers(Vec<SignalHandlerEntry>);
@@ -19,7 +26,7 @@ impl SignalHandlers {
         SignalHandlers(Vec::new())
     }
 
-    pub fn add_gluon(&mut self, pat: &str, func: SignalHandler) -> Result<()> {
+    fn add(&mut self, pat: &str, func: Handler) -> Result<()> {
         self.0.push(SignalHandlerEntry {
             pat: Regex::new(pat).map_err(|_| Error::Regex(pat.to_string()))?,
             func,
@@ -27,12 +34,25 @@ impl SignalHandlers {
         Ok(())
     }
 
+    pub fn add_gluon(&mut self, pat: &str, func: SignalHandler) -> Result<()> {
+        self.add(pat, Handler::Gluon(func))
+    }
+
+    pub fn add_native(&mut self, pat: &str, func: NativeHandler) -> Result<()> {
+        self.add(pat, Handler::Native(func))
+    }
+
     pub fn handle(&mut self, l: &Log) {
         for handler in &mut self.0 {
             if handler.pat.is_match(&l.typ) {
-                if let Err(e) = handler.func.call(l.clone()) {
-                    eprintln!("Error running signal handler:");
-                    print_gluon_err(e.into());
+                match handler.func {
+                    Handler::Gluon(ref mut func) => {
+                        if let Err(e) = func.call(l.clone()) {
+                            eprintln!("Error running signal handler:");
+                            print_gluon_err(e.into());
+                        }
+                    }
+                    Handler::Native(ref mut func) => func(l),
                 }
             }
         }
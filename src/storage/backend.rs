@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sled::{transaction::ConflictableTransactionError, Transactional, Tree};
+
+/// The collections kept by a backend, each mapping binary keys to values, ordered by key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    Meta,
+    Logs,
+    Objs,
+    Jobs,
+}
+
+impl Space {
+    pub const ALL: [Space; 4] = [Space::Meta, Space::Logs, Space::Objs, Space::Jobs];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Space::Meta => "meta",
+            Space::Logs => "logs",
+            Space::Objs => "objs",
+            Space::Jobs => "jobs",
+        }
+    }
+}
+
+/// Makes the new value of an entry from the old one
+pub type Update<'a> = dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>> + 'a;
+
+/// Keys and values of a space
+pub type Entries<'a> = Box<dyn DoubleEndedIterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+
+/// Where the store keeps its records
+pub trait Backend: Send {
+    fn get(&self, space: Space, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn insert(&mut self, space: Space, key: &[u8], value: Vec<u8>);
+
+    fn remove(&mut self, space: Space, key: &[u8]);
+
+    /// Insert all of `entries` at once, so that a crash halfway leaves none of them written
+    fn insert_all(&mut self, entries: Vec<(Space, Vec<u8>, Vec<u8>)>);
+
+    /// Replace the value of `key` with what `f` makes of the old one, removing it if `f` returns `None`.
+    /// Returns the old value
+    fn update(&mut self, space: Space, key: &[u8], f: &mut Update<'_>) -> Option<Vec<u8>>;
+
+    /// All the entries of the space, ordered by key
+    fn iter(&self, space: Space) -> Entries<'_>;
+
+    /// Save a copy of all the records next to the backend's own files, named with `suffix`, that the backend
+    /// can be opened from. Returns where the copy is, or `None` for backends that aren't kept on disk
+    fn backup(&self, suffix: &str) -> io::Result<Option<PathBuf>>;
+}
+
+/// Where a backup of the files at `path` named with `suffix` goes
+pub fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Records kept on disk in a sled database, with a tree per space
+pub struct SledBackend {
+    path: PathBuf,
+    db: sled::Db,
+    trees: Vec<Tree>,
+}
+
+impl SledBackend {
+    pub fn open(path: &Path) -> SledBackend {
+        let db = sled::open(path).unwrap();
+        SledBackend {
+            path: path.into(),
+            trees: Space::ALL.iter().map(|s| db.open_tree(s.name()).unwrap()).collect(),
+            db,
+        }
+    }
+
+    fn tree(&self, space: Space) -> &Tree {
+        &self.trees[space as usize]
+    }
+}
+
+impl Backend for SledBackend {
+    fn get(&self, space: Space, key: &[u8]) -> Option<Vec<u8>> {
+        self.tree(space).get(key).unwrap().map(|v| v.to_vec())
+    }
+
+    fn insert(&mut self, space: Space, key: &[u8], value: Vec<u8>) {
+        self.tree(space).insert(key, value).unwrap();
+    }
+
+    fn remove(&mut self, space: Space, key: &[u8]) {
+        self.tree(space).remove(key).unwrap();
+    }
+
+    fn insert_all(&mut self, entries: Vec<(Space, Vec<u8>, Vec<u8>)>) {
+        self.trees[..]
+            .transaction(|trees| {
+                for (space, key, value) in &entries {
+                    trees[*space as usize].insert(&key[..], &value[..])?;
+                }
+                Ok::<_, ConflictableTransactionError>(())
+            })
+            .unwrap();
+    }
+
+    fn update(&mut self, space: Space, key: &[u8], f: &mut Update<'_>) -> Option<Vec<u8>> {
+        self.tree(space).fetch_and_update(key, f).unwrap().map(|v| v.to_vec())
+    }
+
+    fn iter(&self, space: Space) -> Entries<'_> {
+        Box::new(
+            self.tree(space)
+                .iter()
+                .map(|res| res.unwrap())
+                .map(|(k, v)| (k.to_vec(), v.to_vec())),
+        )
+    }
+
+    fn backup(&self, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let path = backup_path(&self.path, suffix);
+        let backup = sled::open(&path)?;
+        backup.import(self.db.export());
+        backup.flush()?;
+        Ok(Some(path))
+    }
+}
+
+/// Records kept in memory, gone when dropped. For tests and throwaway sessions
+#[derive(Default)]
+pub struct MemoryBackend {
+    spaces: [BTreeMap<Vec<u8>, Vec<u8>>; 4],
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn get(&self, space: Space, key: &[u8]) -> Option<Vec<u8>> {
+        self.spaces[space as usize].get(key).cloned()
+    }
+
+    fn insert(&mut self, space: Space, key: &[u8], value: Vec<u8>) {
+        self.spaces[space as usize].insert(key.to_vec(), value);
+    }
+
+    fn remove(&mut self, space: Space, key: &[u8]) {
+        self.spaces[space as usize].remove(key);
+    }
+
+    fn insert_all(&mut self, entries: Vec<(Space, Vec<u8>, Vec<u8>)>) {
+        for (space, key, value) in entries {
+            self.insert(space, &key, value);
+        }
+    }
+
+    fn update(&mut self, space: Space, key: &[u8], f: &mut Update<'_>) -> Option<Vec<u8>> {
+        let space = &mut self.spaces[space as usize];
+        let old = space.get(key).cloned();
+        match f(old.as_deref()) {
+            Some(new) => space.insert(key.to_vec(), new),
+            None => space.remove(key),
+        };
+        old
+    }
+
+    fn iter(&self, space: Space) -> Entries<'_> {
+        Box::new(self.spaces[space as usize].iter().map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    fn backup(&self, _: &str) -> io::Result<Option<PathBuf>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memory() {
+        let mut backend = MemoryBackend::new();
+        backend.insert(Space::Logs, &[0, 2], b"b".to_vec());
+        backend.insert(Space::Logs, &[0, 1], b"a".to_vec());
+        assert_eq!(backend.get(Space::Logs, &[0, 1]), Some(b"a".to_vec()));
+        assert_eq!(backend.get(Space::Objs, &[0, 1]), None);
+
+        let old = backend.update(Space::Logs, &[0, 1], &mut |old| old.map(|v| [v, b"!"].concat()));
+        assert_eq!(old, Some(b"a".to_vec()));
+        assert_eq!(backend.get(Space::Logs, &[0, 1]), Some(b"a!".to_vec()));
+        assert_eq!(
+            backend.iter(Space::Logs).rev().map(|(k, _)| k).collect::<Vec<_>>(),
+            vec![vec![0, 2], vec![0, 1]]
+        );
+
+        backend.update(Space::Logs, &[0, 2], &mut |_| None);
+        backend.remove(Space::Logs, &[0, 1]);
+        assert_eq!(backend.iter(Space::Logs).count(), 0);
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use serde_cbor::ser::IoWrite;
+use thiserror::Error;
+
+/// First byte of CBOR records. JSON text never starts with a control character, so records starting with one
+/// are versioned binary encodings, and everything else is legacy JSON
+const CBOR_V1: u8 = 0x01;
+
+/// How records are encoded in the backend. Chosen when the store is created and kept in `meta`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    Cbor,
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("Unknown record encoding version {0}")]
+    Version(u8),
+}
+
+/// New stores are compact
+impl Default for Codec {
+    fn default() -> Codec {
+        Codec::Cbor
+    }
+}
+
+impl Codec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::Cbor => "cbor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Codec> {
+        match name {
+            "json" => Some(Codec::Json),
+            "cbor" => Some(Codec::Cbor),
+            _ => None,
+        }
+    }
+
+    /// The codec a record was encoded with
+    pub fn of(bytes: &[u8]) -> Codec {
+        match bytes.first() {
+            Some(&CBOR_V1) => Codec::Cbor,
+            _ => Codec::Json,
+        }
+    }
+
+    pub fn encode<S: ?Sized + Serialize>(self, obj: &S) -> Vec<u8> {
+        match self {
+            Codec::Json => serde_json::to_vec(obj).unwrap(),
+            Codec::Cbor => {
+                let mut bytes = vec![CBOR_V1];
+                obj.serialize(&mut serde_cbor::Serializer::new(IoWrite::new(&mut bytes)))
+                    .unwrap();
+                bytes
+            }
+        }
+    }
+}
+
+/// Read a record in any encoding, whatever the codec of the store is
+pub fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, DecodeError> {
+    match bytes.first() {
+        Some(&CBOR_V1) => Ok(serde_cbor::from_slice(&bytes[1..])?),
+        Some(&b) if b < 0x20 && !b" \t\n\r".contains(&b) => Err(DecodeError::Version(b)),
+        _ => Ok(serde_json::from_slice(bytes)?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn test_codec() {
+        let record =
+            json!({ "name": "water", "typ": "task", "priority": 3, "deadline": { "Single": -1 }, "cache": [] });
+        for &codec in &[Codec::Json, Codec::Cbor] {
+            let bytes = codec.encode(&record);
+            assert_eq!(Codec::of(&bytes), codec);
+            assert_eq!(decode::<Value>(&bytes).unwrap(), record);
+        }
+        assert!(Codec::Cbor.encode(&record).len() < Codec::Json.encode(&record).len());
+        assert_eq!(decode::<Value>(b" \n{}").unwrap(), json!({}));
+        assert!(matches!(decode::<Value>(&[0x02, 0xa0]), Err(DecodeError::Version(2))));
+    }
+}
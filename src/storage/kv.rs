@@ -1,31 +1,27 @@
 use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use chrono::{TimeZone, Utc};
 use serde_json::json;
-use sled::Tree;
 
 use crate::{
+    attrs,
+    clock::Clock,
     script::{
+        job::JobDef,
         sched::{AttrValue, Attrs, Log, Object},
-        task::{Event, Task},
+        task::{Event, EventTime, Task},
         time::{DateTime, Duration},
     },
-    signal::{SignalHandler, SignalHandlers},
-    storage::{Error, OptRepeated, Result},
+    signal::{NativeHandler, SignalHandler, SignalHandlers},
+    storage::{
+        decode, grace, is_closed, migrate, AllDay, Backend, Busy, Codec, Conflicts, Error, FreeBusy, Interval,
+        MemoryBackend, OptRepeated, Result, Space, CONFLICT_HORIZON_DAYS,
+    },
 };
 
-macro_rules! attrs {
-    { $($tt:tt)+ } => {
-        {
-            use ::std::collections::BTreeMap;
-            let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
-            serde_json::json_internal!(@object object () ($($tt)+) ($($tt)+));
-            object
-        }
-    };
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct RawLog {
     typ: String,
@@ -82,20 +78,36 @@ impl From<Object> for RawObject {
     }
 }
 
+/// Timed events are stored with `start` and `duration` fields, and all-day events with an `all-day` field
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawEventTime {
+    Timed {
+        start: OptRepeated,
+        duration: Duration,
+    },
+    AllDay {
+        #[serde(rename = "all-day")]
+        all_day: AllDay,
+    },
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct RawEvent {
     #[serde(flatten)]
     pub object: RawObject,
-    pub start: OptRepeated,
-    pub duration: Duration,
+    #[serde(flatten)]
+    pub time: RawEventTime,
 }
 
 impl RawEvent {
     fn with_id(self, id: u32) -> Event {
         Event {
             object: self.object.with_id(id),
-            start: self.start,
-            duration: self.duration,
+            time: match self.time {
+                RawEventTime::Timed { start, duration } => EventTime::Timed(start, duration),
+                RawEventTime::AllDay { all_day } => EventTime::AllDay(all_day),
+            },
         }
     }
 }
@@ -138,18 +150,14 @@ impl From<Task> for RawTask {
 
 // FIXME limit range of logs to only logs or handlers
 pub struct Storage {
-    meta: Tree,
-    logs: Tree,
-    objs: Tree,
+    backend: Box<dyn Backend>,
     handlers: SignalHandlers,
-}
-
-fn ser<S: ?Sized + serde::Serialize>(obj: &S) -> Vec<u8> {
-    serde_json::to_vec(obj).unwrap()
+    clock: Arc<dyn Clock>,
+    codec: Codec,
 }
 
 fn deser<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8]) -> T {
-    serde_json::from_slice(bytes).unwrap()
+    decode(bytes).unwrap()
 }
 
 fn ser_id(id: u32) -> Vec<u8> {
@@ -160,58 +168,104 @@ fn deser_id(bytes: &[u8]) -> u32 {
     u32::from_be_bytes(bytes.try_into().expect("malformed id in binary"))
 }
 
+/// Where the database is kept
+pub fn db_path() -> PathBuf {
+    dirs::config_dir().unwrap().join("sched").join("sched.db") // FIXME
+}
+
 impl Storage {
-    pub fn new() -> Storage {
-        let config_dir = dirs::config_dir().unwrap().join("sched"); // FIXME
-        let db = sled::open(config_dir.join("sched.db")).unwrap();
-        let meta = db.open_tree("meta").unwrap();
-        if !meta.contains_key("logs_id").unwrap() {
-            meta.insert("logs_id", ser_id(1u32)).unwrap();
+    /// Wrap `backend`, bringing its records to the current schema version first. New stores encode their
+    /// records with `codec`, and others keep the codec they were created with
+    pub fn new(mut backend: Box<dyn Backend>, clock: Arc<dyn Clock>, codec: Codec) -> Result<Storage> {
+        if backend.get(Space::Meta, b"logs_id").is_none() {
+            migrate::init(&mut *backend);
+            backend.insert(Space::Meta, b"codec", codec.name().into());
         }
-        if !meta.contains_key("objs_id").unwrap() {
-            meta.insert("objs_id", ser_id(1u32)).unwrap();
+        // Stores from before codecs were kept are JSON
+        let codec = match backend.get(Space::Meta, b"codec") {
+            Some(name) => {
+                let name = String::from_utf8_lossy(&name);
+                Codec::from_name(&name).ok_or_else(|| Error::UnknownCodec(name.into()))?
+            }
+            None => Codec::Json,
+        };
+        let report = migrate::upgrade(&mut *backend, false)?;
+        if !report.steps.is_empty() {
+            eprintln!("{}", report);
         }
-        Storage {
-            meta,
-            logs: db.open_tree("logs").unwrap(),
-            objs: db.open_tree("objs").unwrap(),
-            handlers: SignalHandlers::new(),
+        for key in &["logs_id", "objs_id", "jobs_id"] {
+            if backend.get(Space::Meta, key.as_bytes()).is_none() {
+                backend.insert(Space::Meta, key.as_bytes(), ser_id(1u32));
+            }
         }
+        Ok(Storage {
+            backend,
+            handlers: SignalHandlers::new(),
+            clock,
+            codec,
+        })
+    }
+
+    /// An empty store that is gone when dropped
+    pub fn in_memory(clock: Arc<dyn Clock>) -> Storage {
+        Storage::new(Box::new(MemoryBackend::new()), clock, Codec::default())
+            .expect("new stores are at the current schema")
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// The current time according to the clock of the store
+    pub fn now(&self) -> chrono::DateTime<Utc> {
+        self.clock.now()
     }
 
     pub fn add_gluon(&mut self, pat: &str, f: SignalHandler) -> Result<()> {
         self.handlers.add_gluon(pat, f)
     }
 
+    pub fn add_native(&mut self, pat: &str, f: NativeHandler) -> Result<()> {
+        self.handlers.add_native(pat, f)
+    }
+
+    pub fn get_meta<T: serde::de::DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        self.backend.get(Space::Meta, key.as_bytes()).map(|v| deser(&v))
+    }
+
+    pub fn set_meta<T: serde::Serialize>(&mut self, key: &str, val: &T) {
+        self.backend.insert(Space::Meta, key.as_bytes(), self.codec.encode(val));
+    }
+
     fn get_log_id(&mut self) -> u32 {
         deser_id(
             &self
-                .meta
-                .fetch_and_update("logs_id", |old| Some(ser_id(deser_id(old.unwrap()) + 1)))
-                .unwrap()
+                .backend
+                .update(Space::Meta, b"logs_id", &mut |old| {
+                    Some(ser_id(deser_id(old.unwrap()) + 1))
+                })
                 .unwrap(),
         )
     }
 
     pub fn create_log(&mut self, typ: String, attrs: Attrs) -> Result<u32> {
         let id = self.get_log_id();
-        let time = Utc::now().into();
+        let time = self.now().into();
         let raw = RawLog { typ, attrs, time };
-        self.logs.insert(ser_id(id), ser(&raw)).unwrap();
+        self.backend.insert(Space::Logs, &ser_id(id), self.codec.encode(&raw));
         let log = raw.with_id(id);
         self.handlers.handle(&log);
         Ok(id)
     }
 
     pub fn log_add_attr_raw(&mut self, id: u32, key: String, val: AttrValue) -> Result<()> {
-        self.logs
-            .fetch_and_update(ser_id(id), |old| {
-                let mut log: RawLog = deser(old.unwrap());
-                // FIXME key val cloned cuz captured by closure; use batch?
-                log.attrs.entry(key.clone()).or_insert(val.clone());
-                Some(ser(&log))
-            })
-            .unwrap();
+        let codec = self.codec;
+        self.backend.update(Space::Logs, &ser_id(id), &mut |old| {
+            let mut log: RawLog = deser(old.unwrap());
+            // FIXME key val cloned cuz captured by closure; use batch?
+            log.attrs.entry(key.clone()).or_insert(val.clone());
+            Some(codec.encode(&log))
+        });
         Ok(())
     }
 
@@ -224,18 +278,16 @@ impl Storage {
     }
 
     pub fn get_log(&mut self, id: u32) -> Result<Log> {
-        self.logs
-            .get(ser_id(id))
-            .unwrap()
+        self.backend
+            .get(Space::Logs, &ser_id(id))
             .map(|l| deser::<RawLog>(&l).with_id(id))
             .ok_or(Error::InvalidLogID(id))
     }
 
     pub fn find_log<F: Fn(&Log) -> bool>(&mut self, filter: F, limit: Option<usize>) -> Vec<Log> {
-        self.logs
-            .iter()
+        self.backend
+            .iter(Space::Logs)
             .rev()
-            .map(|res| res.unwrap())
             .map(|(k, v)| deser::<RawLog>(&v).with_id(deser_id(&k)))
             .filter(filter)
             .take(limit.unwrap_or(1))
@@ -245,73 +297,74 @@ impl Storage {
     fn get_obj_id(&mut self) -> u32 {
         deser_id(
             &self
-                .meta
-                .fetch_and_update("objs_id", |old| Some(ser_id(deser_id(old.unwrap()) + 1)))
-                .unwrap()
+                .backend
+                .update(Space::Meta, b"objs_id", &mut |old| {
+                    Some(ser_id(deser_id(old.unwrap()) + 1))
+                })
                 .unwrap(),
         )
     }
 
     pub fn create_obj(&mut self, name: &str, typ: &str) -> Result<u32> {
         let id = self.get_obj_id();
-        self.objs
-            .insert(ser_id(id), ser(&json!({ "name": name, "typ": typ })))
-            .unwrap();
+        self.backend.insert(
+            Space::Objs,
+            &ser_id(id),
+            self.codec.encode(&json!({ "name": name, "typ": typ })),
+        );
         self.create_log("obj.create".into(), attrs! { "id": id })?;
         Ok(id)
     }
 
     pub fn obj_set_desc(&mut self, id: u32, desc: String) -> Result<()> {
+        let codec = self.codec;
         let mut attrs = None;
-        self.objs
-            .fetch_and_update(ser_id(id), |old| {
-                let mut obj: RawObject = deser(old.unwrap());
-                if obj.desc.is_empty() {
-                    attrs = Some(attrs! { "id": id, "new": desc });
-                } else {
-                    attrs = Some(attrs! { "id": id, "old": obj.desc, "new": desc });
-                }
-                // FIXME desc cloned cuz captured by closure; use batch?
-                obj.desc = desc.clone();
-                Some(ser(&obj))
-            })
-            .unwrap();
+        self.backend.update(Space::Objs, &ser_id(id), &mut |old| {
+            let mut obj: RawObject = deser(old.unwrap());
+            if obj.desc.is_empty() {
+                attrs = Some(attrs! { "id": id, "new": desc });
+            } else {
+                attrs = Some(attrs! { "id": id, "old": obj.desc, "new": desc });
+            }
+            // FIXME desc cloned cuz captured by closure; use batch?
+            obj.desc = desc.clone();
+            Some(codec.encode(&obj))
+        });
         self.create_log("obj.set_desc".into(), attrs.unwrap())?;
         Ok(())
     }
 
     pub fn obj_set_attr(&mut self, id: u32, key: String, val: AttrValue) -> Result<()> {
+        let codec = self.codec;
         let mut attrs = None;
-        self.objs
-            .fetch_and_update(ser_id(id), |old| {
-                let mut obj: RawObject = deser(old.unwrap());
-                if obj.attrs.contains_key(&key) {
-                    attrs = Some(attrs! { "id": id, "old": obj.attrs[&key], "new": val });
-                } else {
-                    attrs = Some(attrs! { "id": id, "new": val });
-                }
-                // FIXME key val cloned cuz captured by closure; use batch?
-                obj.attrs.insert(key.clone(), val.clone());
-                Some(ser(&obj))
-            })
-            .unwrap();
+        self.backend.update(Space::Objs, &ser_id(id), &mut |old| {
+            let mut obj: RawObject = deser(old.unwrap());
+            if obj.attrs.contains_key(&key) {
+                attrs = Some(attrs! { "id": id, "old": obj.attrs[&key], "new": val });
+            } else {
+                attrs = Some(attrs! { "id": id, "new": val });
+            }
+            // FIXME key val cloned cuz captured by closure; use batch?
+            obj.attrs.insert(key.clone(), val.clone());
+            Some(codec.encode(&obj))
+        });
         self.create_log("obj.set_attr".into(), attrs.unwrap())?;
         Ok(())
     }
 
     pub fn obj_del_attr(&mut self, id: u32, key: &str) -> Result<()> {
+        let codec = self.codec;
         let mut attrs = None;
-        self.objs
+        self.backend
             // FIXME Conditionally don't need update
-            .fetch_and_update(ser_id(id), |old| {
+            .update(Space::Objs, &ser_id(id), &mut |old| {
                 let mut obj: RawObject = deser(old.unwrap());
                 if obj.attrs.contains_key(key) {
                     attrs = Some(attrs! { "id": id, "old": obj.attrs[key] });
                     obj.attrs.remove(key);
                 }
-                Some(ser(&obj))
-            })
-            .unwrap();
+                Some(codec.encode(&obj))
+            });
         if let Some(attrs) = attrs {
             self.create_log("obj.set_attr".into(), attrs)?;
         }
@@ -319,18 +372,16 @@ impl Storage {
     }
 
     pub fn get_obj(&mut self, id: u32) -> Result<Object> {
-        self.objs
-            .get(ser_id(id))
-            .unwrap()
+        self.backend
+            .get(Space::Objs, &ser_id(id))
             .map(|o| deser::<RawObject>(&o).with_id(id))
             .ok_or(Error::InvalidObjID(id))
     }
 
     pub fn find_obj<F: Fn(&Object) -> bool>(&mut self, filter: F, limit: Option<usize>) -> Vec<Object> {
-        self.objs
-            .iter()
+        self.backend
+            .iter(Space::Objs)
             .rev()
-            .map(|res| res.unwrap())
             .map(|(k, v)| deser::<RawObject>(&v).with_id(deser_id(&k)))
             .filter(filter)
             .take(limit.unwrap_or(1))
@@ -345,6 +396,7 @@ impl Storage {
         priority: u32,
         attrs: Option<Attrs>,
     ) -> Result<u32> {
+        deadline.validate()?;
         // FIXME use batch (atomic) or transaction sematics
         let id = self.get_obj_id();
         let mut task = RawTask {
@@ -383,7 +435,7 @@ impl Storage {
                 }
             }
         }
-        self.objs.insert(ser_id(id), ser(&task)).unwrap();
+        self.backend.insert(Space::Objs, &ser_id(id), self.codec.encode(&task));
         self.create_log("task.create".into(), attrs! { "id": id })?;
         Ok(id)
     }
@@ -393,9 +445,8 @@ impl Storage {
     }
 
     fn get_raw_task(&mut self, id: u32) -> Result<RawTask> {
-        self.objs
-            .get(ser_id(id))
-            .unwrap()
+        self.backend
+            .get(Space::Objs, &ser_id(id))
             .map(|t| deser::<RawTask>(&t))
             .ok_or(Error::ObjNotTask(id))
     }
@@ -406,7 +457,36 @@ impl Storage {
 
     // FIXME Error on finished tasks? Or how to handle collision
     pub fn task_finish(&mut self, id: u32, finished: DateTime) -> Result<()> {
-        self.log_add_attr_raw(id, "finished".into(), serde_json::to_value(finished).unwrap())?;
+        self.task_close(id, "finished", finished, "task.finish")
+    }
+
+    /// Close a daughter task without finishing it. Skipped tasks count as missed in statistics
+    pub fn task_skip(&mut self, id: u32, skipped: DateTime) -> Result<()> {
+        self.task_close(id, "skipped", skipped, "task.skip")
+    }
+
+    /// Move the deadline of a daughter task by `by`, so that it can become overdue again
+    pub fn task_postpone(&mut self, id: u32, by: Duration) -> Result<DateTime> {
+        let codec = self.codec;
+        let log = self.get_log(id)?;
+        let deadline = match log.attrs.get("deadline").and_then(|v| v.as_i64()) {
+            Some(deadline) if log.typ == "task.task" => DateTime::from(Utc.timestamp(deadline, 0) + by.0),
+            _ => return Err(Error::LogNotTask(id)),
+        };
+        self.backend.update(Space::Logs, &ser_id(id), &mut |old| {
+            let mut log: RawLog = deser(old.unwrap());
+            log.attrs
+                .insert("deadline".into(), serde_json::to_value(deadline).unwrap());
+            log.attrs.remove("overdue");
+            Some(codec.encode(&log))
+        });
+        self.create_log("task.postpone".into(), attrs! { "id": id, "deadline": deadline })?;
+        Ok(deadline)
+    }
+
+    /// Mark a daughter task as closed with `attr`, and generate the next daughter task for repeated tasks
+    fn task_close(&mut self, id: u32, attr: &str, time: DateTime, typ: &str) -> Result<()> {
+        self.log_add_attr_raw(id, attr.into(), serde_json::to_value(time).unwrap())?;
         let task_log_id = self
             .get_log(id)?
             .attrs
@@ -432,16 +512,17 @@ impl Storage {
                 .unwrap_or(10);
             let cache_size = cache_size + gen_ahead + 1;
             if let Some(next_time) = repeat.next() {
-                let new_id = self.new_daughter_task(id, next_time)?;
+                let new_id = self.new_daughter_task(task_log_id, next_time)?;
                 // We only generate one cuz there can be only 1 task completed
                 task.cache.push(new_id);
                 if task.cache.len() > cache_size as usize {
                     task.cache.remove(0);
                 }
             }
-            self.objs.insert(ser_id(id), ser(&task)).unwrap();
+            self.backend
+                .insert(Space::Objs, &ser_id(task_log_id), self.codec.encode(&task));
         }
-        self.create_log("task.finish".into(), attrs! { "id": id })?;
+        self.create_log(typ.into(), attrs! { "id": id })?;
         Ok(())
     }
 
@@ -452,29 +533,121 @@ impl Storage {
         start: OptRepeated,
         duration: Duration,
         attrs: Option<Attrs>,
+        conflicts: Conflicts,
     ) -> Result<u32> {
+        start.validate()?;
+        let overlaps = match conflicts {
+            Conflicts::Allow => Vec::new(),
+            _ => self.find_conflicts(&start, duration)?,
+        };
+        if let (Conflicts::Reject, Some((other, _))) = (conflicts, overlaps.first()) {
+            return Err(Error::EventConflict(other.event));
+        }
         let id = self.get_obj_id();
         let j = if let Some(attrs) = attrs {
             json!({ "name": name, "typ": "event", "task-typ": typ, "start": start, "duration": duration, "attrs": attrs })
         } else {
             json!({ "name": name, "typ": "event", "task-typ": typ, "start": start, "duration": duration })
         };
-        self.objs.insert(ser_id(id), ser(&j)).unwrap();
+        self.backend.insert(Space::Objs, &ser_id(id), self.codec.encode(&j));
+        self.create_log("event.create".into(), attrs! { "id": id })?;
+        for (other, time) in overlaps {
+            self.create_log(
+                "event.conflict".into(),
+                attrs! { "id": id, "with": other.event, "time": time },
+            )?;
+        }
+        Ok(id)
+    }
+
+    /// Occurrences of all timed events overlapping `[from, to)`. All-day events don't make anyone busy
+    pub fn event_busy(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Busy>> {
+        let ids = self
+            .find_obj(|o| o.typ == "event", Some(usize::MAX))
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<Vec<_>>();
+        let mut busy = Vec::new();
+        for id in ids {
+            let event = self.get_event(id)?;
+            if let EventTime::Timed(..) = event.time {
+                busy.extend(
+                    event
+                        .occurrences(from, to)
+                        .into_iter()
+                        .filter(|i| i.start < i.end)
+                        .map(|interval| Busy { event: id, interval }),
+                );
+            }
+        }
+        Ok(busy)
+    }
+
+    /// Busy intervals and free gaps in `[from, to)`, with repeated events expanded
+    pub fn free_busy(&mut self, from: DateTime, to: DateTime) -> Result<FreeBusy> {
+        Ok(FreeBusy::new(self.event_busy(from, to)?, from, to))
+    }
+
+    /// Existing event occurrences overlapping occurrences of a new event, with the start times of the new
+    /// occurrences. Repeated events are only checked up to `CONFLICT_HORIZON_DAYS` ahead
+    fn find_conflicts(&mut self, start: &OptRepeated, duration: Duration) -> Result<Vec<(Busy, DateTime)>> {
+        let (from, to) = match start {
+            OptRepeated::Single(time) => (*time, *time),
+            OptRepeated::Repeat(_) => {
+                let now = DateTime::from(self.now());
+                (now, DateTime(now.0 + chrono::Duration::days(CONFLICT_HORIZON_DAYS)))
+            }
+        };
+        let starts = start.occurrences(from, to);
+        let (first, last) = match (starts.first(), starts.last()) {
+            (Some(first), Some(last)) => (*first, DateTime(last.0 + duration.0)),
+            _ => return Ok(Vec::new()),
+        };
+        let busy = self.event_busy(first, last)?;
+        let mut conflicts = Vec::new();
+        for start in starts {
+            let interval = Interval {
+                start,
+                end: DateTime(start.0 + duration.0),
+            };
+            conflicts.extend(
+                busy.iter()
+                    .filter(|b| b.interval.overlaps(&interval))
+                    .map(|b| (*b, start)),
+            );
+        }
+        Ok(conflicts)
+    }
+
+    /// Create an all-day event. These are never checked for conflicts
+    pub fn create_all_day_event(
+        &mut self,
+        name: &str,
+        typ: &str,
+        all_day: AllDay,
+        attrs: Option<Attrs>,
+    ) -> Result<u32> {
+        let id = self.get_obj_id();
+        let j = if let Some(attrs) = attrs {
+            json!({ "name": name, "typ": "event", "task-typ": typ, "all-day": all_day, "attrs": attrs })
+        } else {
+            json!({ "name": name, "typ": "event", "task-typ": typ, "all-day": all_day })
+        };
+        self.backend.insert(Space::Objs, &ser_id(id), self.codec.encode(&j));
         self.create_log("event.create".into(), attrs! { "id": id })?;
         Ok(id)
     }
 
     pub fn get_event(&mut self, id: u32) -> Result<Event> {
-        self.objs
-            .get(ser_id(id))
-            .unwrap()
+        self.backend
+            .get(Space::Objs, &ser_id(id))
             .map(|e| deser::<RawEvent>(&e).with_id(id))
             .ok_or(Error::ObjNotEvent(id))
     }
 
     pub fn find_current(&mut self, id: u32) -> Result<Option<u32>> {
         // It should
-        let current_utc = Utc::now();
+        let current_utc = self.now();
         let task = self.get_raw_task(id)?;
         // FIXME better name?
         let balanced = task
@@ -487,7 +660,7 @@ impl Storage {
             .cache
             .iter()
             .map(|&i| self.get_log(i).unwrap())
-            .filter(|l| !l.attrs.contains_key("finished"))
+            .filter(|l| !is_closed(&l.attrs))
             .collect::<Vec<_>>();
 
         let deadlines = unfinished
@@ -495,7 +668,7 @@ impl Storage {
             .map(|task| Utc.timestamp(task.attrs.get("deadline").unwrap().as_i64().unwrap(), 0))
             .collect::<Vec<_>>();
         let len = unfinished.len();
-        let grace = chrono::Duration::minutes(5);
+        let grace = grace(&task.object.attrs);
         // TODO sort this instead so that past unfinished tasks maybe current?
         for i in 0..len {
             let deadline = deadlines[i];
@@ -510,4 +683,63 @@ impl Storage {
         }
         Ok(None)
     }
+
+    /// All unfinished daughter tasks with their mother tasks, and the time they become overdue
+    pub fn unfinished_daughters(&mut self) -> Result<Vec<(Task, Log, chrono::DateTime<Utc>)>> {
+        let ids = self
+            .find_obj(|o| o.typ == "task", Some(usize::MAX))
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<Vec<_>>();
+        let mut daughters = Vec::new();
+        for id in ids {
+            let task = self.get_task(id)?;
+            let grace = grace(&task.object.attrs);
+            for &i in &task.cache {
+                let log = self.get_log(i)?;
+                if is_closed(&log.attrs) {
+                    continue;
+                }
+                if let Some(deadline) = log.attrs.get("deadline").and_then(|v| v.as_i64()) {
+                    daughters.push((task.clone(), log, Utc.timestamp(deadline, 0) + grace));
+                }
+            }
+        }
+        Ok(daughters)
+    }
+
+    fn get_job_id(&mut self) -> u32 {
+        deser_id(
+            &self
+                .backend
+                .update(Space::Meta, b"jobs_id", &mut |old| {
+                    Some(ser_id(deser_id(old.unwrap()) + 1))
+                })
+                .unwrap(),
+        )
+    }
+
+    pub fn create_job(&mut self, def: &JobDef) -> Result<u32> {
+        let key = self.get_job_id();
+        self.backend.insert(Space::Jobs, &ser_id(key), self.codec.encode(def));
+        Ok(key)
+    }
+
+    pub fn set_job(&mut self, key: u32, def: &JobDef) -> Result<()> {
+        self.backend.insert(Space::Jobs, &ser_id(key), self.codec.encode(def));
+        Ok(())
+    }
+
+    pub fn del_job(&mut self, key: u32) -> Result<()> {
+        self.backend.remove(Space::Jobs, &ser_id(key));
+        Ok(())
+    }
+
+    pub fn get_jobs(&mut self) -> Result<Vec<(u32, JobDef)>> {
+        Ok(self
+            .backend
+            .iter(Space::Jobs)
+            .map(|(k, v)| (deser_id(&k), deser(&v)))
+            .collect())
+    }
 }
@@ -0,0 +1,225 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use crate::clock;
+use crate::storage::{decode, Backend, Codec, Error, Result, Space};
+
+/// Version of the records written by this sched
+pub const SCHEMA_VERSION: u32 = 1;
+
+const VERSION_KEY: &[u8] = b"schema_version";
+
+/// A step from the previous schema version to `version`
+pub struct Migration {
+    pub version: u32,
+    pub desc: &'static str,
+    /// Change a record of a space in place, returning whether it changed. `meta` isn't migrated, as its
+    /// values aren't all records
+    pub record: fn(Space, &mut Value) -> bool,
+}
+
+/// The steps to the current schema version, in order
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    desc: "Record the schema version of stores made before it was kept",
+    record: |_, _| false,
+}];
+
+/// What a migration did, or would do for dry runs
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub from: u32,
+    pub to: u32,
+    /// The version, description and number of records changed of each step
+    pub steps: Vec<(u32, &'static str, usize)>,
+    pub backup: Option<PathBuf>,
+    pub dry_run: bool,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.steps.is_empty() {
+            return write!(f, "The store is up to date at schema version {}", self.to);
+        }
+        let verb = if self.dry_run { "Would migrate" } else { "Migrated" };
+        write!(f, "{} the store from schema version {} to {}", verb, self.from, self.to)?;
+        for (version, desc, changed) in &self.steps {
+            write!(f, "\n  {}: {} ({} records changed)", version, desc, changed)?;
+        }
+        if let Some(backup) = &self.backup {
+            write!(f, "\nBacked up the old store to {}", backup.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// The schema version of the records in `backend`. Stores from before versions were kept are version 0
+pub fn version(backend: &dyn Backend) -> Result<u32> {
+    match backend.get(Space::Meta, VERSION_KEY) {
+        Some(v) => v[..].try_into().map(u32::from_be_bytes).map_err(|_| {
+            Error::MalformedRecord(
+                Space::Meta.name().into(),
+                String::from_utf8_lossy(VERSION_KEY).into(),
+                format!("expected 4 bytes, got {}", v.len()),
+            )
+        }),
+        None => Ok(0),
+    }
+}
+
+fn set_version(backend: &mut dyn Backend, version: u32) {
+    backend.insert(Space::Meta, VERSION_KEY, version.to_be_bytes().to_vec());
+}
+
+/// Mark a new store as being at the current version, as there's nothing to migrate
+pub fn init(backend: &mut dyn Backend) {
+    set_version(backend, SCHEMA_VERSION);
+}
+
+/// Bring the records of `backend` to the current schema version, after backing them up. Dry runs only
+/// report what would change
+pub fn upgrade(backend: &mut dyn Backend, dry_run: bool) -> Result<Report> {
+    run(backend, MIGRATIONS, dry_run)
+}
+
+fn run(backend: &mut dyn Backend, migrations: &[Migration], dry_run: bool) -> Result<Report> {
+    let from = version(backend)?;
+    let to = migrations.last().map_or(0, |m| m.version);
+    if from > to {
+        return Err(Error::NewerSchema(from, to));
+    }
+    let pending = migrations.iter().filter(|m| m.version > from).collect::<Vec<_>>();
+    let mut report = Report {
+        from,
+        to,
+        steps: pending.iter().map(|m| (m.version, m.desc, 0)).collect(),
+        backup: None,
+        dry_run,
+    };
+    if pending.is_empty() {
+        return Ok(report);
+    }
+
+    let mut changes = Vec::new();
+    for &space in &[Space::Logs, Space::Objs, Space::Jobs] {
+        for (key, value) in backend.iter(space) {
+            let mut record: Value = decode(&value).map_err(|e| {
+                // Keys of records are ids
+                let id = key[..].try_into().map_or_else(
+                    |_| String::from_utf8_lossy(&key).into_owned(),
+                    |id| u32::from_be_bytes(id).to_string(),
+                );
+                Error::MalformedRecord(space.name().into(), id, e.to_string())
+            })?;
+            let mut changed = false;
+            for (step, m) in pending.iter().enumerate() {
+                if (m.record)(space, &mut record) {
+                    report.steps[step].2 += 1;
+                    changed = true;
+                }
+            }
+            if changed {
+                // Records keep the encoding they were written with
+                changes.push((space, key, Codec::of(&value).encode(&record)));
+            }
+        }
+    }
+    if dry_run {
+        return Ok(report);
+    }
+
+    let suffix = format!("v{}-backup-{}", from, clock::now().format("%Y%m%dT%H%M%S"));
+    report.backup = backend.backup(&suffix).map_err(|e| Error::Backup(e.to_string()))?;
+    // Written together with the version, so that an interrupted migration doesn't leave records migrated
+    // under the old version, to be migrated again
+    changes.push((Space::Meta, VERSION_KEY.to_vec(), to.to_be_bytes().to_vec()));
+    backend.insert_all(changes);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::storage::MemoryBackend;
+
+    /// Rename the `typ` of logs to `type`
+    fn rename(space: Space, record: &mut Value) -> bool {
+        match (space, record.as_object_mut()) {
+            (Space::Logs, Some(log)) => match log.remove("typ") {
+                Some(typ) => {
+                    log.insert("type".into(), typ);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let migrations = [
+            Migration {
+                version: 1,
+                desc: "baseline",
+                record: |_, _| false,
+            },
+            Migration {
+                version: 2,
+                desc: "rename",
+                record: rename,
+            },
+        ];
+        let mut backend = MemoryBackend::new();
+        let log = json!({ "typ": "task.finish", "time": 0 });
+        backend.insert(Space::Logs, &[0, 0, 0, 1], serde_json::to_vec(&log).unwrap());
+        backend.insert(
+            Space::Objs,
+            &[0, 0, 0, 1],
+            br#"{"name": "water", "typ": "task"}"#.to_vec(),
+        );
+
+        let report = run(&mut backend, &migrations, true).unwrap();
+        assert_eq!(report.steps, vec![(1, "baseline", 0), (2, "rename", 1)]);
+        assert_eq!(version(&backend).unwrap(), 0);
+        assert_eq!(
+            backend.get(Space::Logs, &[0, 0, 0, 1]),
+            Some(serde_json::to_vec(&log).unwrap())
+        );
+
+        let report = run(&mut backend, &migrations, false).unwrap();
+        assert_eq!((report.from, report.to, report.backup), (0, 2, None));
+        assert_eq!(version(&backend).unwrap(), 2);
+        let log: Value = serde_json::from_slice(&backend.get(Space::Logs, &[0, 0, 0, 1]).unwrap()).unwrap();
+        assert_eq!(log, json!({ "type": "task.finish", "time": 0 }));
+
+        // Only the steps past the stored version run
+        set_version(&mut backend, 1);
+        assert_eq!(
+            run(&mut backend, &migrations, true).unwrap().steps,
+            vec![(2, "rename", 0)]
+        );
+        assert!(run(&mut backend, &migrations[..1], false).unwrap().steps.is_empty());
+        set_version(&mut backend, 3);
+        assert!(run(&mut backend, &migrations, false).is_err());
+
+        set_version(&mut backend, 1);
+        backend.insert(Space::Objs, &[0, 0, 0, 7], b"{".to_vec());
+        let err = run(&mut backend, &migrations, true).unwrap_err().to_string();
+        assert!(err.starts_with("Malformed record 7 in objs: "), "{}", err);
+
+        backend.insert(Space::Meta, VERSION_KEY, vec![1]);
+        let err = run(&mut backend, &migrations, true).unwrap_err().to_string();
+        assert_eq!(err, "Malformed record schema_version in meta: expected 4 bytes, got 1");
+    }
+
+    #[test]
+    fn test_schema_version() {
+        assert_eq!(MIGRATIONS.last().map(|m| m.version), Some(SCHEMA_VERSION));
+    }
+}
@@ -1,11 +1,39 @@
+mod backend;
+mod codec;
 mod kv;
+pub mod migrate;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
+pub use backend::*;
+pub use codec::*;
 pub use kv::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
 
-use chrono::Datelike;
+use std::convert::TryFrom;
+
+use chrono::{Datelike, NaiveDate, TimeZone};
 use thiserror::Error;
 
-use crate::script::time::{DateTime, Duration};
+use crate::cron::Cron;
+use crate::script::{
+    sched::Attrs,
+    time::{add_months, Date, DateTime, Duration},
+};
+
+/// Build an `Attrs` map with `json!` syntax
+#[macro_export]
+macro_rules! attrs {
+    { $($tt:tt)+ } => {
+        {
+            use ::std::collections::BTreeMap;
+            let mut object: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+            serde_json::json_internal!(@object object () ($($tt)+) ($($tt)+));
+            object
+        }
+    };
+}
 
 // FIXME define types (newtype?) for log and object IDs
 #[derive(Clone, Debug, Trace, VmType, Pushable, Getable, Error)]
@@ -25,10 +53,116 @@ pub enum Error {
     ObjNotTask(u32),
     #[error("Object with id {0} is not an Event")]
     ObjNotEvent(u32),
+    #[error("Log with id {0} is not a daughter task")]
+    LogNotTask(u32),
+    #[error("A focus session is already running on task {0}")]
+    FocusActive(u32),
+    #[error("No focus session is running")]
+    NoFocus,
+    #[error("Can't find job function '{0}'")]
+    JobFunc(String),
+    #[error("Schedule has no occurrences")]
+    EmptySchedule,
+    #[error("Invalid reminder offset '{0}'")]
+    InvalidOffset(String),
+    #[error("Event conflicts with event {0}")]
+    EventConflict(u32),
+    #[error("Invalid cron expression '{0}': {1}")]
+    InvalidCron(String, String),
+    #[error("The store has schema version {0}, newer than version {1} this sched knows")]
+    NewerSchema(u32, u32),
+    #[error("Malformed record {1} in {0}: {2}")]
+    MalformedRecord(String, String, String),
+    #[error("Can't back up the store: {0}")]
+    Backup(String),
+    #[error("Unknown record codec {0}")]
+    UnknownCodec(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Default time after a deadline before a daughter task counts as overdue
+const DEFAULT_GRACE_SECS: u64 = 300;
+
+/// Grace period of a task, set by the `grace` attribute in seconds
+pub fn grace(attrs: &Attrs) -> chrono::Duration {
+    let secs = attrs
+        .get("grace")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_GRACE_SECS);
+    chrono::Duration::seconds(secs as i64)
+}
+
+/// What to do when a new event overlaps with existing ones
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub enum Conflicts {
+    Allow,
+    /// Create the event, and log an `event.conflict` for each overlap
+    Warn,
+    Reject,
+}
+
+/// How far ahead repeated events are checked for conflicts
+pub const CONFLICT_HORIZON_DAYS: i64 = 365;
+
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct Interval {
+    pub start: DateTime,
+    /// Exclusive
+    pub end: DateTime,
+}
+
+impl Interval {
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// An occurrence of an event
+#[derive(Clone, Copy, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct Busy {
+    pub event: u32,
+    pub interval: Interval,
+}
+
+#[derive(Clone, Debug, PartialEq, VmType, Pushable, Getable)]
+pub struct FreeBusy {
+    /// Event occurrences overlapping the range, sorted by start
+    pub busy: Vec<Busy>,
+    /// Gaps between the busy intervals within the range
+    pub free: Vec<Interval>,
+}
+
+impl FreeBusy {
+    pub fn new(mut busy: Vec<Busy>, from: DateTime, to: DateTime) -> FreeBusy {
+        busy.sort_by_key(|b| (b.interval.start, b.interval.end));
+        let mut free = Vec::new();
+        let mut cursor = from;
+        for b in &busy {
+            if b.interval.start > cursor {
+                free.push(Interval {
+                    start: cursor,
+                    end: b.interval.start.min(to),
+                });
+            }
+            cursor = cursor.max(b.interval.end);
+            if cursor >= to {
+                break;
+            }
+        }
+        if cursor < to {
+            free.push(Interval { start: cursor, end: to });
+        }
+        free.retain(|i| i.start < i.end);
+        FreeBusy { busy, free }
+    }
+}
+
+/// Whether a daughter task has been finished or skipped
+pub fn is_closed(attrs: &Attrs) -> bool {
+    attrs.contains_key("finished") || attrs.contains_key("skipped")
+}
+
 // FIXME manually implement `Pushable` and `Getable`, so that internal state is not passed to Gluon, and that
 // they are set to reset state when passed from Gluon
 // FIXME use other internal states to record when to stop i.e. can't change the stop properties for public
@@ -50,27 +184,139 @@ pub enum OptRepeated {
     Repeat(Repeated),
 }
 
+impl OptRepeated {
+    /// Check that the times can be computed, so that bad schedules are rejected when they are stored instead of
+    /// quietly stopping later
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            OptRepeated::Single(_) => Ok(()),
+            OptRepeated::Repeat(repeat) => repeat.every.validate(),
+        }
+    }
+
+    /// The times in `[from, to]`
+    pub fn occurrences(&self, from: DateTime, to: DateTime) -> Vec<DateTime> {
+        match self {
+            OptRepeated::Single(time) if from <= *time && *time <= to => vec![*time],
+            OptRepeated::Single(_) => Vec::new(),
+            OptRepeated::Repeat(repeat) => repeat.clone().take_while(|t| *t <= to).filter(|t| *t >= from).collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, VmType, Pushable, Getable)]
 pub enum Every {
     Time(Duration),
     Month(u32),
+    /// A cron expression, evaluated in local time
+    Cron(String),
 }
 
 impl Every {
-    fn advance(&self, time: DateTime) -> DateTime {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Every::Cron(expr) => expr
+                .parse::<Cron>()
+                .map(|_| ())
+                .map_err(|e| Error::InvalidCron(expr.clone(), e.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// The next time after `time`, in a schedule starting at `anchor`. Months are counted from `anchor`, with
+    /// days past the end of a month clamped to its last day, so that clamped days don't carry over
+    fn advance(&self, time: DateTime, anchor: DateTime) -> Option<DateTime> {
         let time = match self {
             Every::Time(dur) => time.0 + dur.0,
             Every::Month(c) => {
-                let month = time.0.month0() + c;
-                let (year_diff, month) = (month / 12, month % 12);
+                let (date, anchor) = (time.0.date().naive_local(), anchor.0.date().naive_local());
+                let months = (date.year() - anchor.year()) * 12 + date.month0() as i32 - anchor.month0() as i32;
+                let date = add_months(anchor, months.checked_add(i32::try_from(*c).ok()?)?)?;
                 time.0
-                    .with_year(time.0.year() + year_diff as i32)
-                    .unwrap()
-                    .with_month0(month)
-                    .expect(&format!("invalid month {}", month))
+                    .offset()
+                    .from_local_datetime(&date.and_time(time.0.time()))
+                    .single()?
+            }
+            Every::Cron(expr) => {
+                // Checked by `validate` when the schedule was stored
+                let cron: Cron = expr.parse().ok()?;
+                cron.next_after(&time.0)?.into()
             }
         };
-        DateTime(time)
+        Some(DateTime(time))
+    }
+}
+
+/// Calendar steps of repeated all-day events
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, VmType, Pushable, Getable)]
+pub enum DateEvery {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
+
+impl DateEvery {
+    fn is_zero(&self) -> bool {
+        match *self {
+            DateEvery::Days(c) | DateEvery::Weeks(c) | DateEvery::Months(c) | DateEvery::Years(c) => c == 0,
+        }
+    }
+
+    /// The date `n` steps after `date`, or `None` past the range of dates. This is computed from `date` every
+    /// time so that clamped month ends don't drift
+    fn nth(&self, date: NaiveDate, n: u32) -> Option<NaiveDate> {
+        let steps = |c: u32, unit: i64| -> Option<i32> {
+            i32::try_from(i64::from(c).checked_mul(unit)?.checked_mul(i64::from(n))?).ok()
+        };
+        let add_days = |days| NaiveDate::from_num_days_from_ce_opt(date.num_days_from_ce().checked_add(days)?);
+        match *self {
+            DateEvery::Days(c) => add_days(steps(c, 1)?),
+            DateEvery::Weeks(c) => add_days(steps(c, 7)?),
+            DateEvery::Months(c) => add_months(date, steps(c, 1)?),
+            DateEvery::Years(c) => add_months(date, steps(c, 12)?),
+        }
+    }
+}
+
+/// The dates of an all-day event, which are the same in any timezone
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, VmType, Pushable, Getable)]
+pub struct AllDay {
+    pub date: Date,
+    /// Number of days the event spans
+    pub days: u32,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub every: Option<DateEvery>,
+    /// The last date an occurrence can start on
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<Date>,
+}
+
+impl AllDay {
+    /// Start dates of the occurrences overlapping the dates `from` to `to`, inclusive
+    pub fn occurrences(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let span = chrono::Duration::days(self.days.max(1) as i64 - 1);
+        let every = match self.every {
+            Some(every) if !every.is_zero() => every,
+            _ if self.date.0 <= to && self.date.0 + span >= from => return vec![self.date.0],
+            _ => return Vec::new(),
+        };
+        let mut dates = Vec::new();
+        for n in 0.. {
+            let start = match every.nth(self.date.0, n) {
+                Some(start) => start,
+                None => break,
+            };
+            if start > to || self.until.map_or(false, |until| start > until.0) {
+                break;
+            }
+            if start + span >= from {
+                dates.push(start);
+            }
+        }
+        dates
     }
 }
 
@@ -115,7 +361,13 @@ impl Iterator for Repeated {
         if let Some(DateTime(mut last)) = self.last {
             if self.index == self.start.len() - 1 {
                 last = last - (self.start[self.start.len() - 1].0 - self.start[0].0);
-                last = self.every.advance(DateTime(last)).0;
+                last = match self.every.advance(DateTime(last), self.start[0]) {
+                    Some(next) => next.0,
+                    None => {
+                        self.stop = Stop::Stopped;
+                        return None;
+                    }
+                };
                 self.index = 0;
             } else {
                 self.index += 1;
@@ -140,29 +392,81 @@ mod test {
     use chrono::prelude::*;
     use chrono::Duration;
 
+    use super::{AllDay, Busy, DateEvery, FreeBusy, Interval};
+
     fn datetime(y: i32, m: u32, d: u32, h: u32, mi: u32, s: u32) -> super::DateTime {
         super::DateTime(Utc.ymd(y, m, d).and_hms(h, mi, s).into())
     }
 
     #[test]
     fn test_every_advance() {
-        use super::Every;
+        use super::{Every, Repeated, Stop};
         let now = datetime(2020, 12, 25, 12, 13, 14);
         assert_eq!(
-            Every::Time(Duration::days(3).into()).advance(now),
-            datetime(2020, 12, 28, 12, 13, 14)
+            Every::Time(Duration::days(3).into()).advance(now, now),
+            Some(datetime(2020, 12, 28, 12, 13, 14))
         );
         assert_eq!(
-            Every::Time(Duration::days(7).into()).advance(now),
-            datetime(2021, 1, 1, 12, 13, 14)
+            Every::Time(Duration::days(7).into()).advance(now, now),
+            Some(datetime(2021, 1, 1, 12, 13, 14))
         );
         assert_eq!(
-            Every::Time(Duration::weeks(1).into()).advance(now),
-            datetime(2021, 1, 1, 12, 13, 14)
+            Every::Time(Duration::weeks(1).into()).advance(now, now),
+            Some(datetime(2021, 1, 1, 12, 13, 14))
+        );
+        assert_eq!(
+            Every::Month(1).advance(now, now),
+            Some(datetime(2021, 1, 25, 12, 13, 14))
+        );
+        assert_eq!(
+            Every::Month(12).advance(now, now),
+            Some(datetime(2021, 12, 25, 12, 13, 14))
+        );
+        assert_eq!(
+            Every::Month(18).advance(now, now),
+            Some(datetime(2022, 6, 25, 12, 13, 14))
+        );
+        let end_of_month = datetime(2021, 1, 31, 12, 13, 14);
+        assert_eq!(
+            Every::Month(1).advance(end_of_month, end_of_month),
+            Some(datetime(2021, 2, 28, 12, 13, 14))
+        );
+        assert_eq!(Every::Month(u32::MAX).advance(now, now), None);
+        assert_eq!(Every::Cron("0 0 30 2 *".into()).advance(now, now), None);
+
+        // Clamped days don't carry over to the next months
+        let monthly = Repeated::new(vec![end_of_month], Every::Month(1), Stop::Nonstop);
+        assert_eq!(
+            monthly.take(3).collect::<Vec<_>>(),
+            vec![
+                end_of_month,
+                datetime(2021, 2, 28, 12, 13, 14),
+                datetime(2021, 3, 31, 12, 13, 14)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_occurrences() {
+        use super::{Every, OptRepeated, Repeated, Stop};
+        let repeat = OptRepeated::Repeat(Repeated::new(
+            vec![datetime(2020, 12, 21, 10, 0, 0)],
+            Every::Time(Duration::days(1).into()),
+            Stop::Nonstop,
+        ));
+        assert_eq!(
+            repeat.occurrences(datetime(2020, 12, 22, 0, 0, 0), datetime(2020, 12, 24, 10, 0, 0)),
+            vec![
+                datetime(2020, 12, 22, 10, 0, 0),
+                datetime(2020, 12, 23, 10, 0, 0),
+                datetime(2020, 12, 24, 10, 0, 0),
+            ]
+        );
+        let single = OptRepeated::Single(datetime(2020, 12, 21, 10, 0, 0));
+        assert_eq!(
+            single.occurrences(datetime(2020, 12, 22, 0, 0, 0), datetime(2020, 12, 24, 0, 0, 0)),
+            vec![]
         );
-        assert_eq!(Every::Month(1).advance(now), datetime(2021, 1, 25, 12, 13, 14));
-        assert_eq!(Every::Month(12).advance(now), datetime(2021, 12, 25, 12, 13, 14));
-        assert_eq!(Every::Month(18).advance(now), datetime(2022, 6, 25, 12, 13, 14));
     }
 
     #[test]
@@ -211,4 +515,175 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_free_busy() {
+        let busy = |event, start, end| Busy {
+            event,
+            interval: Interval {
+                start: datetime(2021, 1, 1, start, 0, 0),
+                end: datetime(2021, 1, 1, end, 0, 0),
+            },
+        };
+        let interval = |start, end| Interval {
+            start: datetime(2021, 1, 1, start, 0, 0),
+            end: datetime(2021, 1, 1, end, 0, 0),
+        };
+        let fb = FreeBusy::new(
+            vec![busy(3, 14, 15), busy(1, 7, 10), busy(2, 9, 11), busy(4, 17, 23)],
+            datetime(2021, 1, 1, 8, 0, 0),
+            datetime(2021, 1, 1, 18, 0, 0),
+        );
+        assert_eq!(fb.busy.iter().map(|b| b.event).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(fb.free, vec![interval(11, 14), interval(15, 17)]);
+        assert!(interval(9, 10).overlaps(&interval(7, 11)));
+        assert!(!interval(9, 10).overlaps(&interval(10, 11)));
+    }
+
+    #[test]
+    fn test_all_day() {
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        let birthday = AllDay {
+            date: crate::script::time::Date(date(2020, 2, 29)),
+            days: 1,
+            every: Some(DateEvery::Years(1)),
+            until: None,
+        };
+        assert_eq!(
+            birthday.occurrences(date(2021, 1, 1), date(2024, 12, 31)),
+            vec![
+                date(2021, 2, 28),
+                date(2022, 2, 28),
+                date(2023, 2, 28),
+                date(2024, 2, 29)
+            ]
+        );
+        let vacation = AllDay {
+            date: crate::script::time::Date(date(2021, 1, 31)),
+            days: 3,
+            every: Some(DateEvery::Months(1)),
+            until: Some(crate::script::time::Date(date(2021, 3, 31))),
+        };
+        assert_eq!(
+            vacation.occurrences(date(2021, 2, 3), date(2021, 12, 31)),
+            vec![date(2021, 2, 28), date(2021, 3, 31)]
+        );
+        assert_eq!(
+            vacation.occurrences(date(2021, 2, 1), date(2021, 2, 1)),
+            vec![date(2021, 1, 31)]
+        );
+        let once = AllDay {
+            every: None,
+            ..vacation
+        };
+        assert_eq!(once.occurrences(date(2021, 2, 3), date(2021, 2, 5)), Vec::new());
+
+        // Steps past the range of dates end the occurrences instead of overflowing
+        for &every in &[
+            DateEvery::Days(u32::MAX),
+            DateEvery::Months(u32::MAX),
+            DateEvery::Years(1 << 30),
+        ] {
+            let far = AllDay {
+                every: Some(every),
+                until: None,
+                ..once.clone()
+            };
+            assert_eq!(
+                far.occurrences(date(2021, 1, 1), NaiveDate::MAX),
+                vec![date(2021, 1, 31)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        use std::sync::Arc;
+
+        use super::{OptRepeated, Storage};
+        use crate::clock::FakeClock;
+
+        let clock = Arc::new(FakeClock::new(Utc.ymd(2021, 3, 10).and_hms(12, 0, 0)));
+        let mut store = Storage::in_memory(clock.clone());
+        let log = store.create_log("test".into(), Default::default()).unwrap();
+        assert_eq!(store.get_log(log).unwrap().time, datetime(2021, 3, 10, 12, 0, 0));
+
+        let deadline = datetime(2021, 3, 10, 13, 0, 0);
+        let task = store
+            .create_task("water", "habit", OptRepeated::Single(deadline), 0, None)
+            .unwrap();
+        let daughter = store.find_current(task).unwrap();
+        assert!(daughter.is_some());
+        // Still current during the grace period, then overdue
+        clock.advance(Duration::minutes(64));
+        assert_eq!(store.find_current(task).unwrap(), daughter);
+        clock.advance(Duration::minutes(2));
+        assert_eq!(store.find_current(task).unwrap(), None);
+        clock.set(Utc.ymd(2021, 3, 9).and_hms(0, 0, 0));
+        assert_eq!(store.find_current(task).unwrap(), daughter);
+    }
+
+    #[test]
+    fn test_finish_repeated() {
+        use std::sync::Arc;
+
+        use super::{Every, OptRepeated, Repeated, Stop, Storage};
+        use crate::clock::FakeClock;
+
+        let clock = Arc::new(FakeClock::new(Utc.ymd(2021, 3, 10).and_hms(8, 0, 0)));
+        let mut store = Storage::in_memory(clock);
+        let every = Repeated::new(
+            vec![datetime(2021, 3, 10, 9, 0, 0)],
+            Every::Time(Duration::days(1).into()),
+            Stop::Nonstop,
+        );
+        let task = store
+            .create_task("water", "habit", OptRepeated::Repeat(every), 0, None)
+            .unwrap();
+        let cache = store.get_task(task).unwrap().cache;
+        store.task_finish(cache[0], datetime(2021, 3, 10, 9, 0, 0)).unwrap();
+
+        // The next daughter goes to the task, not to the daughter that was finished
+        let next = store.get_task(task).unwrap().cache;
+        assert_eq!(next.len(), cache.len() + 1);
+        let daughter = store.get_log(*next.last().unwrap()).unwrap();
+        assert_eq!(daughter.attrs["task-id"], task);
+        assert_eq!(
+            daughter.attrs["deadline"],
+            serde_json::to_value(datetime(2021, 3, 15, 9, 0, 0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_cron() {
+        use super::{Error, Every, OptRepeated, Repeated, Stop, Storage};
+        use crate::clock::SystemClock;
+
+        let mut store = Storage::in_memory(std::sync::Arc::new(SystemClock));
+        let every = |expr: &str| {
+            OptRepeated::Repeat(Repeated::new(
+                vec![datetime(2021, 3, 10, 9, 0, 0)],
+                Every::Cron(expr.into()),
+                Stop::Nonstop,
+            ))
+        };
+        assert!(matches!(
+            store.create_task("water", "habit", every("0 9 * * FOO"), 0, None),
+            Err(Error::InvalidCron(..))
+        ));
+        assert!(matches!(
+            store.create_event(
+                "standup",
+                "work",
+                every("61 * * * *"),
+                Duration::minutes(15).into(),
+                None,
+                super::Conflicts::Allow
+            ),
+            Err(Error::InvalidCron(..))
+        ));
+        assert!(store
+            .create_task("water", "habit", every("0 9 * * MON"), 0, None)
+            .is_ok());
+    }
 }
@@ -0,0 +1,601 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, types::Value as SqlValue, Connection, OptionalExtension, Row};
+use serde_json::{Map, Value};
+
+use crate::storage::{backup_path, decode, Backend, Entries, Space, Update};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS logs (
+    id INTEGER PRIMARY KEY,
+    typ TEXT,
+    time INTEGER,
+    attrs TEXT
+);
+CREATE INDEX IF NOT EXISTS logs_typ ON logs (typ);
+CREATE TABLE IF NOT EXISTS objects (
+    id INTEGER PRIMARY KEY,
+    typ TEXT,
+    name TEXT,
+    desc TEXT,
+    attrs TEXT,
+    -- Fields of the records that don't have a column, as a JSON object
+    extra TEXT
+);
+CREATE TABLE IF NOT EXISTS tasks (
+    id INTEGER PRIMARY KEY REFERENCES objects (id),
+    deadline TEXT,
+    priority INTEGER,
+    task_typ TEXT,
+    cache TEXT
+);
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY REFERENCES objects (id),
+    task_typ TEXT,
+    start TEXT,
+    duration INTEGER,
+    all_day TEXT
+);
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY,
+    def TEXT
+);
+";
+
+/// Records kept in a SQLite database, so that they can be queried with SQL. Logs, objects, tasks and events
+/// get a table each, with names and types as text, and attributes and other fields as JSON text. Records in
+/// any codec are split into columns, and read back as JSON
+pub struct SqliteBackend {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> rusqlite::Result<SqliteBackend> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteBackend {
+            path: path.into(),
+            conn,
+        })
+    }
+
+    /// Copy all the records of `from`, replacing the ones with the same keys. Returns the number of records
+    /// copied
+    pub fn import(&mut self, from: &dyn Backend) -> rusqlite::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut count = 0;
+        for &space in &Space::ALL {
+            for (key, value) in from.iter(space) {
+                put(&tx, space, &key, &value)?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+}
+
+fn id(key: &[u8]) -> i64 {
+    u32::from_be_bytes(key.try_into().expect("malformed id in binary")) as i64
+}
+
+fn id_key(id: i64) -> Vec<u8> {
+    (id as u32).to_be_bytes().to_vec()
+}
+
+fn key_param(space: Space, key: &[u8]) -> SqlValue {
+    match space {
+        Space::Meta => SqlValue::Text(String::from_utf8(key.to_vec()).expect("meta key in UTF-8")),
+        _ => SqlValue::Integer(id(key)),
+    }
+}
+
+/// Columns of a space, starting with the key, and the key column to filter and sort by
+fn select(space: Space) -> (&'static str, &'static str) {
+    match space {
+        Space::Meta => ("SELECT key, value FROM meta", "key"),
+        Space::Logs => ("SELECT id, typ, time, attrs FROM logs", "id"),
+        Space::Objs => (
+            "SELECT o.id, o.typ, o.name, o.desc, o.attrs, o.extra,
+                t.deadline, t.priority, t.task_typ, t.cache,
+                e.task_typ, e.start, e.duration, e.all_day
+            FROM objects o LEFT JOIN tasks t ON t.id = o.id LEFT JOIN events e ON e.id = o.id",
+            "o.id",
+        ),
+        Space::Jobs => ("SELECT id, def FROM jobs", "id"),
+    }
+}
+
+/// Take a text field out of `record`. Values which aren't strings are left in the record, so that objects keep
+/// them in `extra`
+fn take_text(record: &mut Map<String, Value>, field: &str) -> SqlValue {
+    match record.remove(field) {
+        Some(Value::String(s)) => SqlValue::Text(s),
+        Some(value) => {
+            record.insert(field.into(), value);
+            SqlValue::Null
+        }
+        None => SqlValue::Null,
+    }
+}
+
+/// Integers are kept as they are, so that times and durations can be compared in SQL. Anything else is JSON
+/// text
+fn to_column(value: Value) -> SqlValue {
+    match value {
+        Value::Number(n) if n.is_i64() => SqlValue::Integer(n.as_i64().unwrap()),
+        value => to_json_column(value),
+    }
+}
+
+fn to_json_column(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        value => SqlValue::Text(value.to_string()),
+    }
+}
+
+/// Read a column written by `to_column` or `to_json_column`
+fn from_column(value: SqlValue) -> Option<Value> {
+    match value {
+        SqlValue::Null => None,
+        SqlValue::Integer(i) => Some(i.into()),
+        SqlValue::Real(f) => Some(f.into()),
+        SqlValue::Text(s) => Some(serde_json::from_str(&s).expect("malformed JSON in record column")),
+        SqlValue::Blob(_) => panic!("unexpected blob in record column"),
+    }
+}
+
+fn from_text_column(value: SqlValue) -> Option<Value> {
+    match value {
+        SqlValue::Text(s) => Some(Value::String(s)),
+        value => from_column(value),
+    }
+}
+
+/// Build a JSON record from the named columns of a row, leaving out nulls
+fn record(row: &Row, columns: &[(&str, usize, bool)], mut record: Map<String, Value>) -> rusqlite::Result<Vec<u8>> {
+    for &(name, i, text) in columns {
+        let value = row.get::<_, SqlValue>(i)?;
+        let value = if text {
+            from_text_column(value)
+        } else {
+            from_column(value)
+        };
+        if let Some(value) = value {
+            record.insert(name.into(), value);
+        }
+    }
+    Ok(serde_json::to_vec(&record).unwrap())
+}
+
+fn read_row(space: Space, row: &Row) -> rusqlite::Result<(Vec<u8>, Vec<u8>)> {
+    let value = match space {
+        Space::Meta => return Ok((row.get::<_, String>(0)?.into_bytes(), row.get(1)?)),
+        Space::Logs => record(
+            row,
+            &[("typ", 1, true), ("time", 2, false), ("attrs", 3, false)],
+            Map::new(),
+        )?,
+        Space::Objs => {
+            let extra = match from_column(row.get(5)?) {
+                Some(Value::Object(extra)) => extra,
+                _ => Map::new(),
+            };
+            record(
+                row,
+                &[
+                    ("typ", 1, true),
+                    ("name", 2, true),
+                    ("desc", 3, true),
+                    ("attrs", 4, false),
+                    ("deadline", 6, false),
+                    ("priority", 7, false),
+                    ("task-typ", 8, true),
+                    ("cache", 9, false),
+                    ("task-typ", 10, true),
+                    ("start", 11, false),
+                    ("duration", 12, false),
+                    ("all-day", 13, false),
+                ],
+                extra,
+            )?
+        }
+        Space::Jobs => row.get::<_, String>(1)?.into_bytes(),
+    };
+    Ok((id_key(row.get(0)?), value))
+}
+
+/// Rows fetched from either end of a space as they are iterated over, so that scans which stop early, like
+/// searches for the latest records, don't read the whole table
+struct Pages<'a> {
+    conn: &'a Connection,
+    space: Space,
+    /// Rows fetched from the front and from the back, in key order
+    front: VecDeque<(Vec<u8>, Vec<u8>)>,
+    back: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// Keys of the last rows fetched from each end, between which the rows left to fetch are
+    lo: Option<Vec<u8>>,
+    hi: Option<Vec<u8>>,
+    /// Whether all the rows have been fetched
+    done: bool,
+}
+
+/// Number of rows fetched at once
+const PAGE: usize = 256;
+
+impl<'a> Pages<'a> {
+    fn new(conn: &'a Connection, space: Space) -> Pages<'a> {
+        Pages {
+            conn,
+            space,
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+            lo: None,
+            hi: None,
+            done: false,
+        }
+    }
+
+    /// The next page of rows between `lo` and `hi`, from the back if `rev`
+    fn fetch(&mut self, rev: bool) -> Vec<(Vec<u8>, Vec<u8>)> {
+        if self.done {
+            return Vec::new();
+        }
+        let (select, key_column) = select(self.space);
+        let mut filters = Vec::new();
+        let mut params = Vec::new();
+        for (bound, op) in &[(&self.lo, ">"), (&self.hi, "<")] {
+            if let Some(key) = bound {
+                filters.push(format!("{} {} ?", key_column, op));
+                params.push(key_param(self.space, key));
+            }
+        }
+        let filter = if filters.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", filters.join(" AND "))
+        };
+        let order = if rev { "DESC" } else { "ASC" };
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "{}{} ORDER BY {} {} LIMIT {}",
+                select, filter, key_column, order, PAGE
+            ))
+            .unwrap();
+        let space = self.space;
+        let rows = stmt
+            .query_map(params, |row| read_row(space, row))
+            .unwrap()
+            .map(|res| res.unwrap())
+            .collect::<Vec<_>>();
+        self.done = rows.len() < PAGE;
+        if let Some((key, _)) = rows.last() {
+            if rev {
+                self.hi = Some(key.clone());
+            } else {
+                self.lo = Some(key.clone());
+            }
+        }
+        rows
+    }
+}
+
+impl Iterator for Pages<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.is_empty() {
+            let rows = self.fetch(false);
+            self.front.extend(rows);
+        }
+        // Once everything is fetched, the rest was fetched from the back
+        self.front.pop_front().or_else(|| self.back.pop_front())
+    }
+}
+
+impl DoubleEndedIterator for Pages<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back.is_empty() {
+            for row in self.fetch(true) {
+                self.back.push_front(row);
+            }
+        }
+        self.back.pop_back().or_else(|| self.front.pop_back())
+    }
+}
+
+fn get(conn: &Connection, space: Space, key: &[u8]) -> rusqlite::Result<Option<Vec<u8>>> {
+    let (select, key_column) = select(space);
+    conn.query_row(
+        &format!("{} WHERE {} = ?", select, key_column),
+        params![key_param(space, key)],
+        |row| read_row(space, row).map(|(_, value)| value),
+    )
+    .optional()
+}
+
+fn delete(conn: &Connection, space: Space, key: &[u8]) -> rusqlite::Result<()> {
+    let tables: &[&str] = match space {
+        Space::Meta => &["meta"],
+        Space::Logs => &["logs"],
+        Space::Objs => &["tasks", "events", "objects"],
+        Space::Jobs => &["jobs"],
+    };
+    let key_column = if space == Space::Meta { "key" } else { "id" };
+    for table in tables {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE {} = ?", table, key_column),
+            params![key_param(space, key)],
+        )?;
+    }
+    Ok(())
+}
+
+fn put(conn: &Connection, space: Space, key: &[u8], value: &[u8]) -> rusqlite::Result<()> {
+    delete(conn, space, key)?;
+    if space == Space::Meta {
+        conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?, ?)",
+            params![key_param(space, key), value],
+        )?;
+        return Ok(());
+    }
+    if space == Space::Jobs {
+        conn.execute(
+            "INSERT INTO jobs (id, def) VALUES (?, ?)",
+            params![id(key), decode::<Value>(value).unwrap().to_string()],
+        )?;
+        return Ok(());
+    }
+    let mut record = match decode(value).unwrap() {
+        Value::Object(record) => record,
+        other => panic!("record isn't an object: {}", other),
+    };
+    let take = |record: &mut Map<String, Value>, field: &str| record.remove(field).unwrap_or(Value::Null);
+    if space == Space::Logs {
+        conn.execute(
+            "INSERT INTO logs (id, typ, time, attrs) VALUES (?, ?, ?, ?)",
+            params![
+                id(key),
+                take_text(&mut record, "typ"),
+                to_column(take(&mut record, "time")),
+                to_json_column(take(&mut record, "attrs"))
+            ],
+        )?;
+        return Ok(());
+    }
+    let typ = take_text(&mut record, "typ");
+    let name = take_text(&mut record, "name");
+    let desc = take_text(&mut record, "desc");
+    let attrs = take(&mut record, "attrs");
+    // Rows of the tasks and events tables, which refer to the object row
+    let (insert, columns) = match &typ {
+        SqlValue::Text(typ) if typ == "task" => (
+            "INSERT INTO tasks (id, deadline, priority, task_typ, cache) VALUES (?, ?, ?, ?, ?)",
+            vec![
+                to_json_column(take(&mut record, "deadline")),
+                to_column(take(&mut record, "priority")),
+                take_text(&mut record, "task-typ"),
+                to_json_column(take(&mut record, "cache")),
+            ],
+        ),
+        SqlValue::Text(typ) if typ == "event" => (
+            "INSERT INTO events (id, task_typ, start, duration, all_day) VALUES (?, ?, ?, ?, ?)",
+            vec![
+                take_text(&mut record, "task-typ"),
+                to_json_column(take(&mut record, "start")),
+                to_column(take(&mut record, "duration")),
+                to_json_column(take(&mut record, "all-day")),
+            ],
+        ),
+        _ => ("", Vec::new()),
+    };
+    let extra = if record.is_empty() {
+        SqlValue::Null
+    } else {
+        SqlValue::Text(Value::Object(record).to_string())
+    };
+    conn.execute(
+        "INSERT INTO objects (id, typ, name, desc, attrs, extra) VALUES (?, ?, ?, ?, ?, ?)",
+        params![id(key), typ, name, desc, to_json_column(attrs), extra],
+    )?;
+    if !insert.is_empty() {
+        conn.execute(insert, std::iter::once(SqlValue::Integer(id(key))).chain(columns))?;
+    }
+    Ok(())
+}
+
+impl Backend for SqliteBackend {
+    fn get(&self, space: Space, key: &[u8]) -> Option<Vec<u8>> {
+        get(&self.conn, space, key).unwrap()
+    }
+
+    fn insert(&mut self, space: Space, key: &[u8], value: Vec<u8>) {
+        let tx = self.conn.transaction().unwrap();
+        put(&tx, space, key, &value).unwrap();
+        tx.commit().unwrap();
+    }
+
+    fn remove(&mut self, space: Space, key: &[u8]) {
+        let tx = self.conn.transaction().unwrap();
+        delete(&tx, space, key).unwrap();
+        tx.commit().unwrap();
+    }
+
+    fn insert_all(&mut self, entries: Vec<(Space, Vec<u8>, Vec<u8>)>) {
+        let tx = self.conn.transaction().unwrap();
+        for (space, key, value) in entries {
+            put(&tx, space, &key, &value).unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    fn update(&mut self, space: Space, key: &[u8], f: &mut Update<'_>) -> Option<Vec<u8>> {
+        let tx = self.conn.transaction().unwrap();
+        let old = get(&tx, space, key).unwrap();
+        match f(old.as_deref()) {
+            Some(new) => put(&tx, space, key, &new).unwrap(),
+            None => delete(&tx, space, key).unwrap(),
+        }
+        tx.commit().unwrap();
+        old
+    }
+
+    fn iter(&self, space: Space) -> Entries<'_> {
+        Box::new(Pages::new(&self.conn, space))
+    }
+
+    fn backup(&self, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let path = backup_path(&self.path, suffix);
+        self.conn
+            .execute("VACUUM INTO ?", params![path.to_string_lossy().into_owned()])
+            .map_err(io::Error::other)?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::NO_PARAMS;
+    use serde_json::json;
+
+    use super::*;
+    use crate::storage::{Codec, MemoryBackend};
+
+    #[test]
+    fn test_round_trip() {
+        let rec = |value: Value| Codec::Cbor.encode(&value);
+        let records = vec![
+            (Space::Meta, b"logs_id".to_vec(), vec![0, 0, 0, 3]),
+            (
+                Space::Logs,
+                id_key(1),
+                rec(
+                    json!({ "typ": "task.task", "time": 1615388400, "attrs": { "task-id": 2, "deadline": 1615392000 } }),
+                ),
+            ),
+            (
+                Space::Logs,
+                id_key(2),
+                Codec::Json.encode(&json!({ "typ": "obj.create", "time": { "ms": 1615388400123i64 } })),
+            ),
+            (
+                Space::Objs,
+                id_key(1),
+                // Text which looks like JSON stays text
+                rec(json!({ "name": "42", "typ": "note", "desc": "[1, 2]", "color": "red" })),
+            ),
+            (
+                Space::Objs,
+                id_key(2),
+                rec(json!({
+                    "name": "water", "typ": "task", "desc": "8 glasses", "attrs": { "gen-ahead": 2 },
+                    "deadline": { "Single": 1615392000 }, "priority": 1, "task-typ": "habit", "cache": [1],
+                })),
+            ),
+            (
+                Space::Objs,
+                id_key(3),
+                rec(
+                    json!({ "name": "standup", "typ": "event", "task-typ": "work", "start": { "Single": 1615392000 }, "duration": 900 }),
+                ),
+            ),
+            (
+                Space::Objs,
+                id_key(4),
+                rec(
+                    json!({ "name": "trip", "typ": "event", "task-typ": "", "all-day": { "date": "2021-03-10", "days": 3 }, "desc": 7 }),
+                ),
+            ),
+            (
+                Space::Jobs,
+                id_key(1),
+                rec(json!({ "func": "reminders.water", "args": null })),
+            ),
+        ];
+        let mut memory = MemoryBackend::new();
+        for (space, key, value) in &records {
+            memory.insert(*space, key, value.clone());
+        }
+        let mut sqlite = SqliteBackend::open(Path::new(":memory:")).unwrap();
+        assert_eq!(sqlite.import(&memory).unwrap(), records.len());
+        for (space, key, value) in &records {
+            let read = sqlite.get(*space, key).unwrap();
+            if *space == Space::Meta {
+                assert_eq!(&read, value);
+            } else {
+                let read: Value = serde_json::from_slice(&read).unwrap();
+                assert_eq!(read, decode::<Value>(value).unwrap());
+            }
+        }
+        assert_eq!(
+            sqlite.iter(Space::Objs).map(|(k, _)| id(&k)).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+
+        // Tasks can be queried by their columns
+        let deadline: String = sqlite
+            .conn
+            .query_row(
+                "SELECT deadline FROM tasks WHERE task_typ = 'habit'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(deadline, r#"{"Single":1615392000}"#);
+
+        let old = sqlite.update(Space::Objs, &id_key(2), &mut |old| {
+            let mut task: Value = serde_json::from_slice(old.unwrap()).unwrap();
+            task["typ"] = "note".into();
+            Some(serde_json::to_vec(&task).unwrap())
+        });
+        assert!(old.is_some());
+        let count: i64 = sqlite
+            .conn
+            .query_row("SELECT count(*) FROM tasks", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+        sqlite.remove(Space::Objs, &id_key(3));
+        assert_eq!(sqlite.get(Space::Objs, &id_key(3)), None);
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut sqlite = SqliteBackend::open(Path::new(":memory:")).unwrap();
+        let count = PAGE as i64 * 2 + 10;
+        for i in 1..=count {
+            let log = json!({ "typ": "test", "time": i });
+            sqlite.insert(Space::Logs, &id_key(i), Codec::Json.encode(&log));
+        }
+        let ids =
+            |entries: &mut dyn Iterator<Item = (Vec<u8>, Vec<u8>)>| entries.map(|(k, _)| id(&k)).collect::<Vec<_>>();
+        assert_eq!(ids(&mut sqlite.iter(Space::Logs)), (1..=count).collect::<Vec<_>>());
+        assert_eq!(
+            ids(&mut sqlite.iter(Space::Logs).rev()),
+            (1..=count).rev().collect::<Vec<_>>()
+        );
+
+        // Both ends meet without skipping or repeating rows
+        let mut entries = sqlite.iter(Space::Logs);
+        let mut seen = Vec::new();
+        while let Some((key, _)) = entries.next_back() {
+            seen.push(id(&key));
+            if let Some((key, _)) = entries.next() {
+                seen.push(id(&key));
+            }
+        }
+        seen.sort();
+        assert_eq!(seen, (1..=count).collect::<Vec<_>>());
+        assert_eq!(sqlite.iter(Space::Jobs).count(), 0);
+    }
+}
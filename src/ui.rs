@@ -0,0 +1,773 @@
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, Write};
+use std::iter;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use termion::{
+    clear, color, cursor, event::Key, input::TermRead, raw::IntoRawMode, screen::AlternateScreen, style, terminal_size,
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    clock::LocalZone,
+    script::{
+        agenda::{self, Item, ItemKind, Status},
+        sched::{wait_store, AttrValue, Attrs, Log},
+        task::Task,
+        time::{Date, DateTime, Duration},
+    },
+    storage::{grace, Interval, Result as StorageResult, Storage},
+    util::fit,
+};
+
+/// How often the view is refreshed without any input or logs, so that statuses stay current
+const TICK: StdDuration = StdDuration::from_secs(30);
+/// Number of days shown in the agenda pane
+const AGENDA_DAYS: i64 = 3;
+/// Number of logs kept in the log pane
+const MAX_LOGS: usize = 200;
+/// Smallest terminal size the panes fit in with a line each, and the status line
+const MIN_SIZE: (u16, u16) = (20, 7);
+
+enum Event {
+    Key(Key),
+    Log(Log),
+    Tick,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pane {
+    Agenda,
+    Tasks,
+    Logs,
+}
+
+impl Pane {
+    fn next(self) -> Pane {
+        match self {
+            Pane::Agenda => Pane::Tasks,
+            Pane::Tasks => Pane::Logs,
+            Pane::Logs => Pane::Agenda,
+        }
+    }
+
+    fn prev(self) -> Pane {
+        self.next().next()
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Prompt {
+    Postpone,
+    SetAttr,
+}
+
+impl Prompt {
+    fn label(self) -> &'static str {
+        match self {
+            Prompt::Postpone => "Postpone by (default 1h): ",
+            Prompt::SetAttr => "Set attribute (key=value): ",
+        }
+    }
+}
+
+/// A mother task with its current daughter task
+struct TaskRow {
+    task: Task,
+    current: Option<Log>,
+}
+
+struct Rect {
+    x: u16,
+    y: u16,
+    w: u16,
+    h: u16,
+}
+
+/// A line of a pane, with the escape codes to style it
+type Line = (String, String);
+
+struct App {
+    focus: Pane,
+    agenda: Vec<Item>,
+    tasks: Vec<TaskRow>,
+    /// Newest first
+    logs: VecDeque<Log>,
+    selected: [usize; 3],
+    details: Vec<String>,
+    prompt: Option<(Prompt, String)>,
+    /// Result of the last action
+    message: String,
+    /// Time of the last reload, which statuses are shown at
+    now: DateTime,
+}
+
+fn show_time(t: &DateTime, format: &str) -> String {
+    t.0.with_timezone(&LocalZone).format(format).to_string()
+}
+
+fn show_attrs(attrs: &Attrs) -> Vec<String> {
+    attrs.iter().map(|(k, v)| format!("  {}: {}", k, v)).collect()
+}
+
+fn deadline(log: &Log) -> Option<DateTime> {
+    let deadline = log.attrs.get("deadline")?.as_i64()?;
+    Some(Utc.timestamp(deadline, 0).into())
+}
+
+fn status_style(status: Status) -> String {
+    match status {
+        Status::Upcoming => String::new(),
+        Status::Current => format!("{}{}", color::Fg(color::Yellow), style::Bold),
+        Status::Overdue => format!("{}{}", color::Fg(color::Red), style::Bold),
+        Status::Done => color::Fg(color::LightBlack).to_string(),
+    }
+}
+
+impl App {
+    fn new(now: DateTime) -> App {
+        App {
+            focus: Pane::Agenda,
+            agenda: Vec::new(),
+            tasks: Vec::new(),
+            logs: VecDeque::new(),
+            selected: [0; 3],
+            details: Vec::new(),
+            prompt: None,
+            message: String::new(),
+            now,
+        }
+    }
+
+    fn len(&self, pane: Pane) -> usize {
+        match pane {
+            Pane::Agenda => self.agenda.len(),
+            Pane::Tasks => self.tasks.len(),
+            Pane::Logs => self.logs.len(),
+        }
+    }
+
+    fn reload(&mut self, store: &mut Storage) -> StorageResult<()> {
+        let now = DateTime::from(store.now());
+        self.now = now;
+        let today = Date::of(&now);
+        let range = Interval {
+            start: today.start(),
+            end: Date(today.0 + chrono::Duration::days(AGENDA_DAYS)).start(),
+        };
+        self.agenda = agenda::agenda_with(store, range, now)?
+            .into_iter()
+            .flat_map(|day| day.items)
+            .collect();
+
+        let ids = store
+            .find_obj(|o| o.typ == "task", Some(usize::MAX))
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<Vec<_>>();
+        self.tasks.clear();
+        for id in ids {
+            let current = match store.find_current(id)? {
+                Some(log) => Some(store.get_log(log)?),
+                None => None,
+            };
+            self.tasks.push(TaskRow {
+                task: store.get_task(id)?,
+                current,
+            });
+        }
+
+        if self.logs.is_empty() {
+            self.logs = store.find_log(|_| true, Some(MAX_LOGS)).into();
+        }
+        for &pane in &[Pane::Agenda, Pane::Tasks, Pane::Logs] {
+            let len = self.len(pane);
+            let selected = &mut self.selected[pane.index()];
+            *selected = (*selected).min(len.saturating_sub(1));
+        }
+        self.update_details(store)
+    }
+
+    fn push_log(&mut self, log: Log) {
+        self.logs.push_front(log);
+        self.logs.truncate(MAX_LOGS);
+        // Keep the selection on the same log
+        if self.selected[Pane::Logs.index()] != 0 {
+            self.selected[Pane::Logs.index()] += 1;
+        }
+    }
+
+    fn selected(&self, pane: Pane) -> usize {
+        self.selected[pane.index()]
+    }
+
+    /// The daughter task actions apply to
+    fn selected_daughter(&self) -> Option<u32> {
+        match self.focus {
+            Pane::Agenda => self.agenda.get(self.selected(Pane::Agenda)).and_then(|i| i.log),
+            Pane::Tasks => self
+                .tasks
+                .get(self.selected(Pane::Tasks))
+                .and_then(|t| t.current.as_ref())
+                .map(|l| l.id),
+            Pane::Logs => self
+                .logs
+                .get(self.selected(Pane::Logs))
+                .filter(|l| l.typ == "task.task")
+                .map(|l| l.id),
+        }
+    }
+
+    /// The task or event attributes are set on
+    fn selected_object(&self) -> Option<u32> {
+        match self.focus {
+            Pane::Agenda => self.agenda.get(self.selected(Pane::Agenda)).map(|i| i.id),
+            Pane::Tasks => self.tasks.get(self.selected(Pane::Tasks)).map(|t| t.task.object.id),
+            Pane::Logs => None,
+        }
+    }
+
+    fn update_details(&mut self, store: &mut Storage) -> StorageResult<()> {
+        let mut details = Vec::new();
+        if let Some(id) = self.selected_object() {
+            let obj = store.get_obj(id)?;
+            details.push(format!("{} ({} {})", obj.name, obj.typ, obj.id));
+            if !obj.desc.is_empty() {
+                details.push(obj.desc.clone());
+            }
+            details.extend(show_attrs(&obj.attrs));
+        }
+        let log = match self.focus {
+            Pane::Logs => self.logs.get(self.selected(Pane::Logs)).cloned(),
+            _ => match self.selected_daughter() {
+                Some(id) => Some(store.get_log(id)?),
+                None => None,
+            },
+        };
+        if let Some(log) = log {
+            if !details.is_empty() {
+                details.push(String::new());
+            }
+            details.push(format!("{} {} at {}", log.typ, log.id, show_time(&log.time, "%F %T")));
+            if let Some(deadline) = deadline(&log) {
+                details.push(format!("  deadline: {}", show_time(&deadline, "%F %T")));
+            }
+            details.extend(show_attrs(&log.attrs));
+        }
+        self.details = details;
+        Ok(())
+    }
+
+    fn agenda_lines(&self) -> Vec<Line> {
+        self.agenda
+            .iter()
+            .map(|i| {
+                let time = match i.kind {
+                    ItemKind::AllDay => format!("{} all day", show_time(&i.time, "%a %d")),
+                    _ => show_time(&i.time, "%a %d %H:%M  "),
+                };
+                let kind = match i.kind {
+                    ItemKind::Task => " [task]",
+                    _ => "",
+                };
+                (status_style(i.status), format!("{} {}{}", time, i.name, kind))
+            })
+            .collect()
+    }
+
+    fn task_lines(&self) -> Vec<Line> {
+        let now = self.now.0;
+        self.tasks
+            .iter()
+            .map(|t| {
+                let (style, due) = match t.current.as_ref().and_then(deadline) {
+                    Some(d) if d.0 + grace(&t.task.object.attrs) < now => {
+                        (status_style(Status::Overdue), show_time(&d, "%a %d %H:%M"))
+                    }
+                    Some(d) => (String::new(), show_time(&d, "%a %d %H:%M")),
+                    None => (status_style(Status::Done), "-".into()),
+                };
+                (
+                    style,
+                    format!("{:>4} {:<12} {}", t.task.object.id, due, t.task.object.name),
+                )
+            })
+            .collect()
+    }
+
+    fn log_lines(&self) -> Vec<Line> {
+        self.logs
+            .iter()
+            .map(|l| {
+                let attrs = serde_json::to_string(&l.attrs).unwrap_or_default();
+                (
+                    String::new(),
+                    format!("{} {} {}", show_time(&l.time, "%m-%d %H:%M"), l.typ, attrs),
+                )
+            })
+            .collect()
+    }
+
+    fn draw_pane<W: Write>(
+        &self,
+        out: &mut W,
+        rect: &Rect,
+        title: &str,
+        lines: &[Line],
+        selected: Option<usize>,
+    ) -> Result<()> {
+        let inner = rect.w.saturating_sub(2) as usize;
+        let height = rect.h.saturating_sub(2) as usize;
+        let reset = format!("{}{}", color::Fg(color::Reset), style::Reset);
+        let title_style = if selected.is_some() {
+            format!("{}{}", color::Fg(color::Green), style::Bold)
+        } else {
+            style::Bold.to_string()
+        };
+        let title = fit(&format!(" {} ", title), inner.min(title.width() + 2));
+        let rule = "─".repeat(inner.saturating_sub(title.width() + 1));
+        write!(
+            out,
+            "{}┌─{}{}{}{}┐",
+            cursor::Goto(rect.x, rect.y),
+            title_style,
+            title,
+            reset,
+            rule
+        )?;
+        // Scroll so that the selection is visible
+        let offset = selected.map_or(0, |s| (s + 1).saturating_sub(height));
+        for row in 0..height {
+            let (line_style, text) = lines
+                .get(offset + row)
+                .map(|(s, t)| (s.as_str(), t.as_str()))
+                .unwrap_or(("", ""));
+            let invert = if selected == Some(offset + row) {
+                style::Invert.to_string()
+            } else {
+                String::new()
+            };
+            write!(
+                out,
+                "{}│{}{}{}{}│",
+                cursor::Goto(rect.x, rect.y + 1 + row as u16),
+                line_style,
+                invert,
+                fit(text, inner),
+                reset
+            )?;
+        }
+        write!(
+            out,
+            "{}└{}┘",
+            cursor::Goto(rect.x, rect.y + rect.h - 1),
+            "─".repeat(inner)
+        )?;
+        Ok(())
+    }
+
+    /// Draw the panes on a terminal of `w` columns and `h` rows
+    fn draw<W: Write>(&self, out: &mut W, (w, h): (u16, u16)) -> Result<()> {
+        write!(out, "{}", clear::All)?;
+        if w < MIN_SIZE.0 || h < MIN_SIZE.1 {
+            write!(
+                out,
+                "{}{}{}",
+                cursor::Goto(1, 1),
+                fit("Terminal too small", w as usize),
+                cursor::Hide
+            )?;
+            out.flush()?;
+            return Ok(());
+        }
+        let left = w / 2;
+        let top = (h - 1) / 2;
+        let panes = [
+            (
+                Pane::Agenda,
+                "Agenda",
+                self.agenda_lines(),
+                Rect {
+                    x: 1,
+                    y: 1,
+                    w: left,
+                    h: top,
+                },
+            ),
+            (
+                Pane::Tasks,
+                "Tasks",
+                self.task_lines(),
+                Rect {
+                    x: 1,
+                    y: top + 1,
+                    w: left,
+                    h: h - 1 - top,
+                },
+            ),
+            (
+                Pane::Logs,
+                "Logs",
+                self.log_lines(),
+                Rect {
+                    x: left + 1,
+                    y: top + 1,
+                    w: w - left,
+                    h: h - 1 - top,
+                },
+            ),
+        ];
+        for (pane, title, lines, rect) in &panes {
+            let selected = if *pane == self.focus {
+                Some(self.selected(*pane))
+            } else {
+                None
+            };
+            self.draw_pane(out, rect, title, lines, selected)?;
+        }
+        let details = self
+            .details
+            .iter()
+            .map(|d| (String::new(), d.clone()))
+            .collect::<Vec<_>>();
+        let rect = Rect {
+            x: left + 1,
+            y: 1,
+            w: w - left,
+            h: top,
+        };
+        self.draw_pane(out, &rect, "Details", &details, None)?;
+
+        let status = match &self.prompt {
+            Some((prompt, input)) => format!("{}{}", prompt.label(), input),
+            None if !self.message.is_empty() => self.message.clone(),
+            None => "tab: switch pane  j/k: move  f: finish  s: skip  p: postpone  e: set attribute  q: quit".into(),
+        };
+        write!(out, "{}{}", cursor::Goto(1, h), fit(&status, w as usize))?;
+        if self.prompt.is_some() {
+            write!(out, "{}{}", cursor::Goto(status.width() as u16 + 1, h), cursor::Show)?;
+        } else {
+            write!(out, "{}", cursor::Hide)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn act(&mut self, store: &mut Storage, key: Key) -> StorageResult<String> {
+        let now = DateTime::from(store.now());
+        match key {
+            Key::Char('f') => match self.selected_daughter() {
+                Some(id) => store.task_finish(id, now).map(|_| format!("Finished {}", id)),
+                None => Ok("No task selected".into()),
+            },
+            Key::Char('s') => match self.selected_daughter() {
+                Some(id) => store.task_skip(id, now).map(|_| format!("Skipped {}", id)),
+                None => Ok("No task selected".into()),
+            },
+            Key::Char('p') if self.selected_daughter().is_some() => {
+                self.prompt = Some((Prompt::Postpone, String::new()));
+                Ok(String::new())
+            }
+            Key::Char('e') if self.selected_object().is_some() => {
+                self.prompt = Some((Prompt::SetAttr, String::new()));
+                Ok(String::new())
+            }
+            Key::Char('p') | Key::Char('e') => Ok("Nothing selected".into()),
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn submit(&mut self, store: &mut Storage, prompt: Prompt, input: &str) -> StorageResult<String> {
+        match prompt {
+            Prompt::Postpone => {
+                let by = if input.trim().is_empty() {
+                    Duration(chrono::Duration::hours(1))
+                } else {
+                    match Duration::parse(input) {
+                        Ok(by) => by,
+                        Err(e) => return Ok(e),
+                    }
+                };
+                match self.selected_daughter() {
+                    Some(id) => store
+                        .task_postpone(id, by)
+                        .map(|d| format!("Postponed {} to {}", id, show_time(&d, "%a %d %H:%M"))),
+                    None => Ok("No task selected".into()),
+                }
+            }
+            Prompt::SetAttr => {
+                let (key, val) = match input.find('=') {
+                    Some(i) => (input[..i].trim(), input[i + 1..].trim()),
+                    None => return Ok("Expected key=value".into()),
+                };
+                // Values which aren't valid JSON are taken as strings
+                let val = serde_json::from_str(val).unwrap_or_else(|_| AttrValue::String(val.into()));
+                match self.selected_object() {
+                    Some(id) => store
+                        .obj_set_attr(id, key.into(), val)
+                        .map(|_| format!("Set {} on {}", key, id)),
+                    None => Ok("Nothing selected".into()),
+                }
+            }
+        }
+    }
+
+    /// Handle a key. Returns false to quit
+    fn key(&mut self, store: &mut Storage, key: Key) -> bool {
+        if let Some((prompt, mut input)) = self.prompt.take() {
+            match key {
+                Key::Char('\n') => {
+                    let res = self.submit(store, prompt, &input);
+                    self.message = res.unwrap_or_else(|e| e.to_string());
+                }
+                Key::Esc => self.message.clear(),
+                Key::Backspace => {
+                    input.pop();
+                    self.prompt = Some((prompt, input));
+                }
+                Key::Char(c) => {
+                    input.push(c);
+                    self.prompt = Some((prompt, input));
+                }
+                _ => self.prompt = Some((prompt, input)),
+            }
+            return true;
+        }
+        let len = self.len(self.focus);
+        let selected = &mut self.selected[self.focus.index()];
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => return false,
+            Key::Char('\t') => self.focus = self.focus.next(),
+            Key::BackTab => self.focus = self.focus.prev(),
+            Key::Char('j') | Key::Down => *selected = (*selected + 1).min(len.saturating_sub(1)),
+            Key::Char('k') | Key::Up => *selected = selected.saturating_sub(1),
+            Key::Char('g') | Key::Home => *selected = 0,
+            Key::Char('G') | Key::End => *selected = len.saturating_sub(1),
+            key => {
+                let res = self.act(store, key);
+                self.message = res.unwrap_or_else(|e| e.to_string());
+                return true;
+            }
+        }
+        self.message.clear();
+        if let Err(e) = self.update_details(store) {
+            self.message = e.to_string();
+        }
+        true
+    }
+}
+
+/// Run the full-screen interface until the user quits. The view is refreshed on every new log
+pub fn run() -> Result<()> {
+    let (sender, receiver) = channel();
+    let logs = sender.clone();
+    wait_store().add_native(
+        ".*",
+        Box::new(move |log| {
+            let _ = logs.send(Event::Log(log.clone()));
+        }),
+    )?;
+    thread::spawn(move || {
+        for key in stdin().keys() {
+            match key {
+                Ok(key) if sender.send(Event::Key(key)).is_ok() => (),
+                _ => break,
+            }
+        }
+    });
+
+    let mut screen = AlternateScreen::from(stdout().into_raw_mode()?);
+    let mut app = App::new(wait_store().now().into());
+    if let Err(e) = app.reload(&mut wait_store()) {
+        app.message = e.to_string();
+    }
+    'main: loop {
+        app.draw(&mut screen, terminal_size()?)?;
+        let event = match receiver.recv_timeout(TICK) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => Event::Tick,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let mut reload = false;
+        for event in iter::once(event).chain(receiver.try_iter()) {
+            match event {
+                Event::Key(key) => {
+                    if !app.key(&mut wait_store(), key) {
+                        break 'main;
+                    }
+                }
+                Event::Log(log) => {
+                    app.push_log(log);
+                    reload = true;
+                }
+                Event::Tick => reload = true,
+            }
+        }
+        if reload {
+            if let Err(e) = app.reload(&mut wait_store()) {
+                app.message = e.to_string();
+            }
+        }
+    }
+    write!(screen, "{}", cursor::Show)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        storage::{is_closed, Conflicts, OptRepeated},
+    };
+
+    fn local(h: u32) -> DateTime {
+        LocalZone.ymd(2021, 3, 10).and_hms(h, 0, 0).into()
+    }
+
+    /// An app at noon on a store with an overdue task, a running event and a task due later
+    fn app() -> (App, Storage) {
+        let clock = Arc::new(FakeClock::new(local(12).0.with_timezone(&Utc)));
+        let mut store = Storage::in_memory(clock);
+        store
+            .create_task("water", "habit", OptRepeated::Single(local(9)), 0, None)
+            .unwrap();
+        store
+            .create_task("cook", "chore", OptRepeated::Single(local(18)), 0, None)
+            .unwrap();
+        let lunch = OptRepeated::Single(local(11));
+        store
+            .create_event(
+                "lunch",
+                "meal",
+                lunch,
+                chrono::Duration::hours(2).into(),
+                None,
+                Conflicts::Allow,
+            )
+            .unwrap();
+        let mut app = App::new(store.now().into());
+        app.reload(&mut store).unwrap();
+        (app, store)
+    }
+
+    fn type_in(app: &mut App, store: &mut Storage, input: &str) {
+        for c in input.chars() {
+            app.key(store, Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let (app, _) = app();
+        let agenda = app.agenda_lines();
+        assert_eq!(agenda.len(), 3);
+        assert_eq!(
+            agenda[0],
+            (
+                status_style(Status::Overdue),
+                format!("{} water [task]", show_time(&local(9), "%a %d %H:%M  "))
+            )
+        );
+        assert_eq!(agenda[1].0, status_style(Status::Current));
+        assert!(agenda[1].1.ends_with(" lunch"));
+
+        // Past its grace period, the overdue task has no current daughter
+        let tasks = app.task_lines();
+        let (water, cook) = (&app.tasks[0].task.object, &app.tasks[1].task.object);
+        assert_eq!(
+            tasks[0],
+            (status_style(Status::Done), format!("{:>4} {:<12} water", water.id, "-"))
+        );
+        let due = show_time(&local(18), "%a %d %H:%M");
+        assert_eq!(tasks[1], (String::new(), format!("{:>4} {:<12} cook", cook.id, due)));
+        assert_eq!(app.log_lines().len(), app.logs.len());
+    }
+
+    #[test]
+    fn test_keys() {
+        let (mut app, mut store) = app();
+        // The selection stays within the pane
+        assert!(app.key(&mut store, Key::Char('k')));
+        assert_eq!(app.selected(Pane::Agenda), 0);
+        app.key(&mut store, Key::Char('G'));
+        app.key(&mut store, Key::Down);
+        assert_eq!(app.selected(Pane::Agenda), 2);
+        assert!(app.details[0].starts_with("cook (task "), "{:?}", app.details);
+        app.key(&mut store, Key::Char('g'));
+        assert_eq!(app.selected_daughter(), app.agenda[0].log);
+
+        // Each pane keeps its own selection
+        app.key(&mut store, Key::Char('\t'));
+        assert_eq!(app.focus, Pane::Tasks);
+        app.key(&mut store, Key::Char('j'));
+        app.key(&mut store, Key::BackTab);
+        assert_eq!((app.focus, app.selected(Pane::Agenda)), (Pane::Agenda, 0));
+        assert_eq!(app.selected(Pane::Tasks), 1);
+
+        assert!(!app.key(&mut store, Key::Char('q')));
+    }
+
+    #[test]
+    fn test_actions() {
+        let (mut app, mut store) = app();
+        // Events have no daughter task
+        app.key(&mut store, Key::Char('j'));
+        app.key(&mut store, Key::Char('f'));
+        assert_eq!(app.message, "No task selected");
+
+        app.key(&mut store, Key::Char('j'));
+        let cook = app.selected_daughter().unwrap();
+        app.key(&mut store, Key::Char('p'));
+        assert_eq!(app.prompt, Some((Prompt::Postpone, String::new())));
+        type_in(&mut app, &mut store, "2h\n");
+        assert!(
+            app.message.starts_with(&format!("Postponed {} to ", cook)),
+            "{}",
+            app.message
+        );
+        assert_eq!(deadline(&store.get_log(cook).unwrap()), Some(local(20)));
+
+        app.key(&mut store, Key::Char('e'));
+        type_in(&mut app, &mut store, "color=redd");
+        app.key(&mut store, Key::Backspace);
+        app.key(&mut store, Key::Char('\n'));
+        assert_eq!(store.get_obj(app.agenda[2].id).unwrap().attrs["color"], "red");
+
+        app.key(&mut store, Key::Char('p'));
+        app.key(&mut store, Key::Esc);
+        assert_eq!(app.prompt, None);
+
+        app.key(&mut store, Key::Char('f'));
+        assert_eq!(app.message, format!("Finished {}", cook));
+        assert!(is_closed(&store.get_log(cook).unwrap().attrs));
+    }
+
+    #[test]
+    fn test_draw() {
+        let (app, _) = app();
+        let draw = |size| {
+            let mut out = Vec::new();
+            app.draw(&mut out, size).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        for w in 0..=MIN_SIZE.0 + 1 {
+            for h in 0..=MIN_SIZE.1 + 1 {
+                draw((w, h));
+            }
+        }
+        assert!(draw((MIN_SIZE.0 - 1, 24)).contains("Terminal too small"));
+        let screen = draw((80, 24));
+        assert!(screen.contains("Agenda") && screen.contains("Logs"));
+    }
+}
@@ -1,5 +1,63 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
 use codespan_reporting::term::termcolor::{ColorChoice::Always, StandardStream};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 pub fn print_gluon_err(e: gluon::Error) {
     e.emit(&mut StandardStream::stderr(Always)).unwrap();
 }
+
+/// Cut `s` to `width` terminal columns, marking cuts with an ellipsis, and pad it with spaces
+pub fn fit(s: &str, width: usize) -> String {
+    let len = s.width();
+    if len <= width {
+        return format!("{}{}", s, " ".repeat(width - len));
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        // Leave a column for the ellipsis
+        if used + w >= width {
+            break;
+        }
+        cut.push(c);
+        used += w;
+    }
+    // A wide character may leave a column over
+    format!("{}…{}", cut, " ".repeat(width - used - 1))
+}
+
+/// Copy the directory `from` with everything in it to `to`
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcdef", 5), "abcd…");
+        assert_eq!(fit("日本語", 6), "日本語");
+        assert_eq!(fit("日本語", 3), "日…");
+        assert_eq!(fit("日本語", 4), "日… ");
+    }
+}